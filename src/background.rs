@@ -0,0 +1,359 @@
+use crate::{
+    breaker::CircuitBreaker, callbacks::Callbacks, connection, connection::Connection,
+    console, http, reentrancy, stats, stats::Counters, BackgroundConfig, Level, Logger,
+    LoggerConfig, LoggerError, LoggerResult,
+};
+use codectrl_protobuf_bindings::data::Log;
+use prost::Message;
+use std::{
+    cell::RefCell,
+    sync::{
+        mpsc::{self, Receiver, SyncSender, TrySendError},
+        Arc, Condvar, Mutex, MutexGuard,
+    },
+    thread,
+    time::{Duration, Instant},
+};
+use tokio::runtime;
+
+#[cfg(not(feature = "websocket"))]
+use crate::websocket;
+#[cfg(feature = "websocket")]
+use crate::websocket::WebSocket;
+
+// The sending half of the queue read by the background threads, one per
+// connection. The threads exit once every `Logger` holding a copy of it has
+// been dropped.
+#[derive(Debug, Clone)]
+pub(crate) struct Background {
+    sender: SyncSender<Log>,
+    drop_on_full: bool,
+    max_bytes: Option<usize>,
+    pending: Arc<Pending>,
+}
+
+// The queued logs that the background thread hasn't finished with yet, so that
+// `Logger::flush` can wait for them, and so that their size can be kept within
+// `BackgroundConfig::max_bytes`.
+#[derive(Debug, Default)]
+struct Pending {
+    queued: Mutex<Queued>,
+    drained: Condvar,
+    // Notified whenever a log has been finished with, making room for more.
+    freed: Condvar,
+}
+
+#[derive(Debug, Default)]
+struct Queued {
+    count: usize,
+    // The total serialised size of the logs.
+    bytes: usize,
+}
+
+impl Pending {
+    // Adds a log of `bytes` bytes if that keeps the total within `max_bytes`,
+    // blocking until it does if `wait` is set. A log larger than `max_bytes`
+    // on its own is still let through once nothing else is pending, so that
+    // it can't block forever.
+    fn add(&self, bytes: usize, max_bytes: Option<usize>, wait: bool) -> bool {
+        let fits = |queued: &Queued| {
+            queued.count == 0
+                || !max_bytes.is_some_and(|max_bytes| queued.bytes + bytes > max_bytes)
+        };
+
+        let mut queued = self.lock();
+
+        if wait {
+            queued = self
+                .freed
+                .wait_while(queued, |queued| !fits(queued))
+                .unwrap_or_else(|poisoned| poisoned.into_inner());
+        } else if !fits(&queued) {
+            return false;
+        }
+
+        queued.count += 1;
+        queued.bytes += bytes;
+
+        stats::adjust_gauge("codectrl_queued_logs", 1.0);
+        stats::adjust_gauge("codectrl_queued_bytes", bytes as f64);
+
+        true
+    }
+
+    fn remove(&self, bytes: usize) {
+        let mut queued = self.lock();
+        queued.count = queued.count.saturating_sub(1);
+        queued.bytes = queued.bytes.saturating_sub(bytes);
+
+        stats::adjust_gauge("codectrl_queued_logs", -1.0);
+        stats::adjust_gauge("codectrl_queued_bytes", -(bytes as f64));
+
+        if queued.count == 0 {
+            self.drained.notify_all();
+        }
+
+        self.freed.notify_all();
+    }
+
+    fn lock(&self) -> MutexGuard<'_, Queued> {
+        self.queued
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+impl Background {
+    pub(crate) fn spawn(
+        config: &LoggerConfig,
+        options: &BackgroundConfig,
+        counters: Arc<Counters>,
+        callbacks: Arc<Callbacks>,
+        breaker: Arc<CircuitBreaker>,
+    ) -> LoggerResult<Self> {
+        let (sender, receiver) = mpsc::sync_channel::<Log>(options.capacity);
+        let receiver = Arc::new(Mutex::new(receiver));
+        let pending = Arc::<Pending>::default();
+
+        // Built up front so that failing to build one is reported by
+        // `Logger::new`. Each has a thread of its own, so that the tasks of a
+        // connection, e.g. answering the pings of a WebSocket server, keep
+        // running while the worker waits for the next log.
+        let runtimes = (0..options.connections)
+            .map(|_| {
+                runtime::Builder::new_multi_thread()
+                    .worker_threads(1)
+                    .enable_all()
+                    .build()
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+
+        for (index, rt) in runtimes.into_iter().enumerate() {
+            let worker = Worker {
+                config: config.clone(),
+                receiver: Arc::clone(&receiver),
+                counters: Arc::clone(&counters),
+                callbacks: Arc::clone(&callbacks),
+                breaker: Arc::clone(&breaker),
+                pending: Arc::clone(&pending),
+            };
+
+            thread::Builder::new()
+                .name(format!("codectrl-logger-{index}"))
+                .spawn(move || worker.run(&rt))?;
+
+            Counters::add(&counters.connections, 1);
+        }
+
+        Ok(Self {
+            sender,
+            drop_on_full: options.drop_on_full,
+            max_bytes: options.max_bytes,
+            pending,
+        })
+    }
+
+    pub(crate) fn pending(&self) -> usize { self.pending.lock().count }
+
+    pub(crate) fn pending_bytes(&self) -> usize { self.pending.lock().bytes }
+
+    // Blocks until every queued log has been handled or `timeout` elapses,
+    // returning how many are still pending.
+    pub(crate) fn wait(&self, timeout: Duration) -> usize {
+        let (queued, _) = self
+            .pending
+            .drained
+            .wait_timeout_while(self.pending.lock(), timeout, |queued| queued.count > 0)
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+
+        queued.count
+    }
+
+    // Like `wait`, but without blocking the async runtime it's called from.
+    pub(crate) async fn wait_async(&self, timeout: Duration) -> usize {
+        let started_at = Instant::now();
+
+        loop {
+            let pending = self.pending();
+
+            if pending == 0 || started_at.elapsed() >= timeout {
+                return pending;
+            }
+
+            tokio::time::sleep(Duration::from_millis(5)).await;
+        }
+    }
+
+    // Returns `Ok(false)` if the log was dropped because the queue is full, or
+    // because it would take the queued logs over `max_bytes`.
+    pub(crate) fn queue(&self, log: Log) -> LoggerResult<bool> {
+        let stopped = || {
+            LoggerError::LoggerError(
+                "Can't queue log: Background thread has stopped".to_string(),
+            )
+        };
+
+        let bytes = log.encoded_len();
+
+        // Counted before sending, so that the background thread can't finish
+        // with the log before it's been counted.
+        if !self.pending.add(bytes, self.max_bytes, !self.drop_on_full) {
+            return Ok(false);
+        }
+
+        let ret = if !self.drop_on_full {
+            self.sender.send(log).map(|_| true).map_err(|_| stopped())
+        } else {
+            match self.sender.try_send(log) {
+                Ok(()) => Ok(true),
+                Err(TrySendError::Full(_)) => Ok(false),
+                Err(TrySendError::Disconnected(_)) => Err(stopped()),
+            }
+        };
+
+        if !matches!(ret, Ok(true)) {
+            self.pending.remove(bytes);
+        }
+
+        ret
+    }
+}
+
+// One of the background threads, each of which sends the logs it takes off the
+// queue over a connection of its own.
+struct Worker {
+    config: LoggerConfig,
+    receiver: Arc<Mutex<Receiver<Log>>>,
+    counters: Arc<Counters>,
+    callbacks: Arc<Callbacks>,
+    breaker: Arc<CircuitBreaker>,
+    pending: Arc<Pending>,
+}
+
+impl Worker {
+    fn run(self, rt: &runtime::Runtime) {
+        // Kept between logs so that each one doesn't need a new connection, or
+        // a new buffer to be serialised into for HTTP.
+        let client = RefCell::new(None);
+        let payload = RefCell::new(Vec::new());
+        // Everything on this thread happens within the logger, so logs created
+        // by the callbacks are suppressed.
+        let _inside = reentrancy::enter();
+
+        let config = &self.config;
+        let breaker_config = config.circuit_breaker.as_ref();
+
+        while let Some(log) = self.next() {
+            if self.breaker.allow(breaker_config) {
+                Counters::add(&self.counters.busy_connections, 1);
+
+                let ret = rt.block_on(Logger::send_with_retries(
+                    config,
+                    Some(&self.counters),
+                    || send_log(&client, &payload, &log, config),
+                ));
+
+                Counters::sub(&self.counters.busy_connections, 1);
+
+                self.breaker.record(&ret, breaker_config);
+                self.counters.record(&ret, 1);
+                self.callbacks.fire(&log, &ret);
+
+                if let Err(e) = ret {
+                    eprintln!("Failed to send queued log: {e}");
+                }
+            } else {
+                // Not reported on stderr, as that would be once per log for as
+                // long as the server is down.
+                Counters::add(&self.counters.short_circuited, 1);
+                self.callbacks.fire(&log, &Err(LoggerError::CircuitOpen));
+            }
+
+            self.pending.remove(log.encoded_len());
+        }
+
+        Counters::sub(&self.counters.connections, 1);
+    }
+
+    // The lock is only held while waiting for a log, not while sending it, so
+    // that the other workers can pick up logs in the meantime.
+    fn next(&self) -> Option<Log> {
+        self.receiver
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+            .recv()
+            .ok()
+    }
+}
+
+// The connection kept open by a background thread between logs.
+enum Client {
+    Grpc(Connection),
+    #[cfg(feature = "websocket")]
+    WebSocket(WebSocket),
+}
+
+async fn connect(config: &LoggerConfig) -> LoggerResult<Client> {
+    #[cfg(feature = "websocket")]
+    if let Some(url) = &config.websocket_url {
+        return Ok(Client::WebSocket(WebSocket::connect(url, config).await?));
+    }
+
+    #[cfg(not(feature = "websocket"))]
+    if let Some(url) = &config.websocket_url {
+        // Fails with an error explaining that the feature is disabled.
+        websocket::send_logs(url, [], config).await?;
+    }
+
+    let (host, port) = config.destination(Level::Info);
+
+    Ok(Client::Grpc(connection::connect(host, port, config).await?))
+}
+
+async fn send_log(
+    client: &RefCell<Option<Client>>,
+    payload: &RefCell<Vec<u8>>,
+    log: &Log,
+    config: &LoggerConfig,
+) -> LoggerResult<()> {
+    if let Some(stream) = config.console {
+        return console::write_logs(stream, [log]);
+    }
+
+    if let Some(url) = &config.http_url {
+        // Taken out rather than borrowed across the request.
+        let mut buffer = payload.take();
+        let ret = http::post_log(url, log, &mut buffer, config).await;
+        payload.replace(buffer);
+
+        return ret;
+    }
+
+    // Taken out for the duration of the request, so that a broken connection
+    // gets replaced on the next attempt.
+    let log_client = match client.take() {
+        Some(log_client) => log_client,
+        None => connect(config).await?,
+    };
+
+    match log_client {
+        Client::Grpc(mut connection) => {
+            let ret = connection.send_log(log).await;
+
+            // The connection is still fine if the server itself reported an
+            // error.
+            if matches!(ret, Ok(()) | Err(LoggerError::LogServerError { .. })) {
+                client.replace(Some(Client::Grpc(connection)));
+            }
+
+            ret
+        },
+        #[cfg(feature = "websocket")]
+        Client::WebSocket(mut websocket) => {
+            websocket.send(log).await?;
+            client.replace(Some(Client::WebSocket(websocket)));
+
+            Ok(())
+        },
+    }
+}
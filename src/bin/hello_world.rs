@@ -2,7 +2,9 @@ use codectrl::{Logger, LoggerError};
 
 fn main() -> anyhow::Result<()> {
     fn inner() -> Result<(), LoggerError> {
-        Logger::log("Hello, world!", None, None, None, None)
+        Logger::log("Hello, world!", None, None, None, None)?;
+
+        Ok(())
     }
 
     inner()?;
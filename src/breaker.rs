@@ -0,0 +1,91 @@
+use crate::{CircuitBreakerConfig, CircuitState, LoggerResult};
+use std::{
+    sync::{Mutex, MutexGuard},
+    time::Instant,
+};
+
+// Tracks the consecutive failures of a `Logger` and its clones, so that
+// sending can be stopped for a while once the server seems to be down. Shared
+// with the background thread.
+#[derive(Debug, Default)]
+pub(crate) struct CircuitBreaker {
+    state: Mutex<State>,
+}
+
+#[derive(Debug, Default)]
+struct State {
+    failures: u32,
+    // Set while the circuit is open or half-open.
+    opened_at: Option<Instant>,
+    // Whether the send probing a half-open circuit is still in flight.
+    probing: bool,
+}
+
+impl CircuitBreaker {
+    // Returns whether a send may go ahead. Once the cooldown has passed, one
+    // send at a time is let through to probe whether the server is back. Every
+    // send that's let through has to be followed by a call to `record`.
+    pub(crate) fn allow(&self, config: Option<&CircuitBreakerConfig>) -> bool {
+        let Some(config) = config else {
+            return true;
+        };
+
+        let mut state = self.lock();
+
+        match state.opened_at {
+            None => true,
+            Some(opened_at)
+                if opened_at.elapsed() >= config.cooldown && !state.probing =>
+            {
+                state.probing = true;
+                true
+            },
+            Some(_) => false,
+        }
+    }
+
+    pub(crate) fn record(
+        &self,
+        ret: &LoggerResult<()>,
+        config: Option<&CircuitBreakerConfig>,
+    ) {
+        let Some(config) = config else {
+            return;
+        };
+
+        let mut state = self.lock();
+
+        match ret {
+            Ok(()) => *state = State::default(),
+            Err(_) => {
+                state.failures = state.failures.saturating_add(1);
+
+                // A failed probe opens the circuit again straight away.
+                if state.probing || state.failures >= config.failures {
+                    state.opened_at = Some(Instant::now());
+                }
+
+                state.probing = false;
+            },
+        }
+    }
+
+    pub(crate) fn state(&self, config: Option<&CircuitBreakerConfig>) -> CircuitState {
+        let Some(config) = config else {
+            return CircuitState::Closed;
+        };
+
+        match self.lock().opened_at {
+            None => CircuitState::Closed,
+            Some(opened_at) if opened_at.elapsed() >= config.cooldown =>
+                CircuitState::HalfOpen,
+            Some(_) => CircuitState::Open,
+        }
+    }
+
+    fn lock(&self) -> MutexGuard<'_, State> {
+        self.state
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
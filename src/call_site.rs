@@ -0,0 +1,254 @@
+use crate::{normalize_path, Level, LogOutcome, Logger, LoggerConfig, LoggerResult};
+use std::{any::Any, fmt::Debug, fs, path::Path};
+
+/// Sends a log with the given [`Level`], taking its file name and line number
+/// from where the macro is called, as reported by [`file!`] and [`line!`] at
+/// compile time, e.g.:
+///
+/// ```no_run
+/// use codectrl::Level;
+///
+/// let port = 8080;
+///
+/// codectrl::log!(Level::Info, "Listening on {}", port).unwrap();
+/// codectrl::log!(Level::Warn, port).unwrap();
+/// codectrl::log!(Level::Debug, "Started").unwrap();
+/// ```
+///
+/// Unlike the backtrace that [`Logger::log`] relies on, these are always
+/// accurate, including in builds without debug info. The backtrace is still
+/// captured for the rest of the stack trace, and if its innermost frame isn't
+/// the call site, e.g. because the call site was inlined, a frame for the call
+/// site is added.
+///
+/// A string literal followed by arguments is a format string, which is
+/// formatted along with them as by [`format!`] and sent as plain text the same
+/// way as by [`Logger::message`]. A string literal on its own is sent as plain
+/// text as is, without being formatted. Any other message, including any other
+/// literal, is formatted with [`Debug`] the same way as by [`Logger::log`].
+///
+/// A host and port to send the log to can be given before the level, as in
+/// `codectrl::log!(Some("10.0.0.2"), Some("3002"); Level::Info, port)`,
+/// otherwise they're taken from the config set by [`init`](crate::init).
+///
+/// As with [`Logger::log_lazy`], the message is only evaluated and formatted
+/// once the log is known to pass the configured filters, so it runs in a
+/// closure and can't use `?` or `.await`. It can still be called from async
+/// code running on a tokio runtime, which is blocked while the log is sent.
+///
+/// Returns the same as [`Logger::log`].
+#[macro_export]
+macro_rules! log {
+    (@call_site) => {
+        $crate::CallSite {
+            file: ::std::file!(),
+            line: ::std::line!(),
+            column: ::std::column!(),
+            module_path: ::std::module_path!(),
+            manifest_dir: ::std::env!("CARGO_MANIFEST_DIR"),
+        }
+    };
+    ($host:expr, $port:expr; $level:expr, $format:literal, $($arg:tt)+) => {
+        $crate::Logger::log_text_at(
+            $crate::log!(@call_site),
+            $level,
+            || ::std::format!($format, $($arg)+),
+            $host,
+            $port,
+        )
+    };
+    ($host:expr, $port:expr; $level:expr, $literal:literal $(,)?) => {
+        $crate::Logger::log_literal_at(
+            $crate::log!(@call_site),
+            $level,
+            $literal,
+            $host,
+            $port,
+        )
+    };
+    ($host:expr, $port:expr; $level:expr, $message:expr $(,)?) => {
+        $crate::Logger::log_at(
+            $crate::log!(@call_site),
+            $level,
+            || $message,
+            $host,
+            $port,
+        )
+    };
+    ($level:expr, $($rest:tt)+) => {
+        $crate::log!(None, None; $level, $($rest)+)
+    };
+}
+
+/// Like [`log!`], but only creates and sends the log if `condition` is true,
+/// e.g.:
+///
+/// ```no_run
+/// use codectrl::Level;
+///
+/// let retries = 3;
+///
+/// codectrl::log_if!(retries > 2, Level::Warn, "Retried {} times", retries)
+///     .unwrap();
+/// ```
+///
+/// Unlike [`Logger::log_if`], `condition` is a plain expression rather than a
+/// function, so it can use local variables. The message isn't evaluated if
+/// `condition` is false, in which case [`LogOutcome::Filtered`] is returned. A
+/// host and port can be given before `condition` the same way as for [`log!`].
+#[macro_export]
+macro_rules! log_if {
+    ($host:expr, $port:expr; $condition:expr, $level:expr, $($rest:tt)+) => {
+        if $condition {
+            $crate::log!($host, $port; $level, $($rest)+)
+        } else {
+            ::std::result::Result::<_, $crate::LoggerError>::Ok(
+                $crate::LogOutcome::Filtered,
+            )
+        }
+    };
+    ($condition:expr, $level:expr, $($rest:tt)+) => {
+        $crate::log_if!(None, None; $condition, $level, $($rest)+)
+    };
+}
+
+/// Where [`log!`] was called, captured at compile time. Only meant to be used
+/// by that macro.
+#[doc(hidden)]
+#[derive(Debug, Clone, Copy)]
+pub struct CallSite {
+    pub file: &'static str,
+    pub line: u32,
+    pub column: u32,
+    pub module_path: &'static str,
+    pub manifest_dir: &'static str,
+}
+
+impl CallSite {
+    // `file!` is relative to the root of the workspace that the calling crate
+    // was built in, which is either its manifest directory or one of the
+    // directories above it. Falls back to the path as is, which is relative to
+    // the current directory, if it doesn't exist in any of them.
+    pub(crate) fn file_path(&self, config: &LoggerConfig) -> String {
+        let path = Path::new(self.manifest_dir)
+            .ancestors()
+            .map(|dir| dir.join(self.file))
+            .find(|path| path.is_file())
+            .unwrap_or_else(|| self.file.into());
+
+        let path = if config.canonicalize_paths {
+            fs::canonicalize(&path).unwrap_or(path)
+        } else {
+            path
+        };
+
+        let path = path.to_string_lossy();
+
+        if cfg!(target_os = "windows") {
+            normalize_path(&path)
+        } else {
+            path.into_owned()
+        }
+    }
+}
+
+impl Logger<'_> {
+    /// Like [`Self::log`], but with the call site captured by [`log!`]. Only
+    /// meant to be used by that macro.
+    #[doc(hidden)]
+    pub fn log_at<T: Debug>(
+        call_site: CallSite,
+        level: Level,
+        message: impl FnOnce() -> T,
+        host: Option<&str>,
+        port: Option<&str>,
+    ) -> LoggerResult<LogOutcome> {
+        Self::log_with_call_site(
+            call_site,
+            level,
+            || (format!("{:#?}", message()), std::any::type_name::<T>().to_string()),
+            host,
+            port,
+        )
+    }
+
+    /// Like [`Self::log_at`], but for a literal, which is sent as plain text if
+    /// it's a string. Only meant to be used by [`log!`].
+    #[doc(hidden)]
+    pub fn log_literal_at<T: Debug + 'static>(
+        call_site: CallSite,
+        level: Level,
+        literal: T,
+        host: Option<&str>,
+        port: Option<&str>,
+    ) -> LoggerResult<LogOutcome> {
+        Self::log_with_call_site(
+            call_site,
+            level,
+            || literal_message(literal),
+            host,
+            port,
+        )
+    }
+
+    /// Like [`Self::message`], but with the call site captured by [`log!`].
+    /// Only meant to be used by that macro.
+    #[doc(hidden)]
+    pub fn log_text_at(
+        call_site: CallSite,
+        level: Level,
+        text: impl FnOnce() -> String,
+        host: Option<&str>,
+        port: Option<&str>,
+    ) -> LoggerResult<LogOutcome> {
+        Self::log_with_call_site(
+            call_site,
+            level,
+            || (text(), "text/plain".to_string()),
+            host,
+            port,
+        )
+    }
+
+    // `message` returns the message along with its type. No runtime is passed
+    // on, as `log!` can't be given one, so the log is sent from a thread of its
+    // own if it's called from within a runtime.
+    fn log_with_call_site(
+        call_site: CallSite,
+        level: Level,
+        message: impl FnOnce() -> (String, String),
+        host: Option<&str>,
+        port: Option<&str>,
+    ) -> LoggerResult<LogOutcome> {
+        Self::log_with_level(
+            level,
+            |surround, config| {
+                let (message, message_type) = message();
+
+                crate::create_log_at(
+                    message,
+                    message_type,
+                    surround,
+                    None,
+                    None,
+                    Some(&call_site),
+                    config,
+                )
+            },
+            None,
+            host,
+            port,
+            None,
+        )
+    }
+}
+
+// Returns the message and its type for a literal given to `log!` on its own.
+// Literals can't be told apart by the macro, so string literals are picked out
+// here to be sent as plain text rather than with `Debug`'s quotes.
+pub(crate) fn literal_message<T: Debug + 'static>(literal: T) -> (String, String) {
+    match (&literal as &dyn Any).downcast_ref::<&str>() {
+        Some(text) => (text.to_string(), "text/plain".to_string()),
+        None => (format!("{:#?}", literal), std::any::type_name::<T>().to_string()),
+    }
+}
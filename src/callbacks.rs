@@ -0,0 +1,51 @@
+use crate::LoggerError;
+use codectrl_protobuf_bindings::data::Log;
+use std::{
+    fmt,
+    panic::{self, AssertUnwindSafe},
+    sync::RwLock,
+};
+
+type SuccessCallback = Box<dyn Fn(&Log) + Send + Sync>;
+type FailureCallback = Box<dyn Fn(&Log, &LoggerError) + Send + Sync>;
+
+// The callbacks registered with `Logger::on_success` and `Logger::on_failure`,
+// shared between the clones of a `Logger` and its background thread.
+#[derive(Default)]
+pub(crate) struct Callbacks {
+    pub(crate) on_success: RwLock<Option<SuccessCallback>>,
+    pub(crate) on_failure: RwLock<Option<FailureCallback>>,
+}
+
+impl Callbacks {
+    // Calls the callback matching the outcome of sending `log`. A panicking
+    // callback is caught so that it can't take down the sender with it.
+    pub(crate) fn fire(&self, log: &Log, ret: &Result<(), LoggerError>) {
+        match ret {
+            Ok(()) => {
+                if let Some(callback) = &*self
+                    .on_success
+                    .read()
+                    .unwrap_or_else(|poisoned| poisoned.into_inner())
+                {
+                    let _ = panic::catch_unwind(AssertUnwindSafe(|| callback(log)));
+                }
+            },
+            Err(e) => {
+                if let Some(callback) = &*self
+                    .on_failure
+                    .read()
+                    .unwrap_or_else(|poisoned| poisoned.into_inner())
+                {
+                    let _ = panic::catch_unwind(AssertUnwindSafe(|| callback(log, e)));
+                }
+            },
+        }
+    }
+}
+
+impl fmt::Debug for Callbacks {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Callbacks").finish_non_exhaustive()
+    }
+}
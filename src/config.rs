@@ -0,0 +1,981 @@
+use crate::{Level, SharedSerializer, DEFAULT_MAX_PAYLOAD_SIZE};
+use serde::{Deserialize, Deserializer};
+use std::{
+    collections::BTreeMap,
+    env,
+    fmt::Display,
+    fs, io,
+    path::{Path, PathBuf},
+    str::FromStr,
+    sync::{
+        atomic::{AtomicU8, Ordering},
+        Arc, RwLock,
+    },
+    time::Duration,
+};
+
+/// The error type used when a [`LoggerConfig`] can't be loaded.
+#[derive(thiserror::Error, Debug)]
+pub enum ConfigError {
+    /// An environment variable was set, but its value isn't valid.
+    #[error("Invalid value {value:?} for envvar {name}: {reason}")]
+    InvalidEnvVar {
+        name: &'static str,
+        value: String,
+        reason: String,
+    },
+    /// The config file couldn't be read.
+    #[error("Could not read config file: {0}")]
+    IOError(#[from] io::Error),
+    /// The config file isn't valid TOML or contains invalid values.
+    #[error("Could not parse config file: {0}")]
+    TomlError(#[from] toml::de::Error),
+    /// A field of the config has a value that can't work, see
+    /// [`LoggerConfig::validate`].
+    #[error("Invalid value for {field}: {reason}")]
+    InvalidValue { field: String, reason: String },
+}
+
+// Larger surrounds would mean sending whole files with every log.
+const MAX_SURROUND: u32 = 1000;
+
+static ACTIVE_CONFIG: RwLock<Option<Arc<LoggerConfig>>> = RwLock::new(None);
+// A copy of the active config's level, so that it can be checked at the start
+// of every log without taking the lock. `u8::MAX` until it's first read.
+static MIN_LEVEL: AtomicU8 = AtomicU8::new(u8::MAX);
+
+/// The configuration used by [`Logger`] and [`LogBatch`] whenever a value
+/// isn't passed in explicitly.
+///
+/// [`Logger`]: crate::Logger
+/// [`LogBatch`]: crate::LogBatch
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default)]
+pub struct LoggerConfig {
+    /// The host name or IP address of the gRPC server to connect to. Defaults
+    /// to `127.0.0.1`.
+    pub host: String,
+    /// The port of the `host` gRPC server to connect to. Defaults to `3002`.
+    #[serde(deserialize_with = "deserialize_port")]
+    pub port: String,
+    /// The surround for the generated code snippet. The `surround` passed into
+    /// a logging function explicitly applies to both sides. Defaults to 3.
+    pub surround: Surround,
+    /// Whether to capture the stack trace, and with it the file name, line
+    /// number and code snippet, of each log. Defaults to `true`.
+    pub capture_stack: bool,
+    /// If set, at most this many stack frames are captured per log, counting
+    /// from the call site outwards. Defaults to `None`.
+    pub max_stack_frames: Option<usize>,
+    /// The names of crates whose frames are left out of the stack trace, e.g.
+    /// `tokio`. Defaults to none.
+    pub skip_crates: Vec<String>,
+    /// Whether to read the code snippet of each log, as well as the line of
+    /// code of each stack frame, from the source files. Defaults to `true`.
+    pub capture_snippet: bool,
+    /// The directory that was embedded with [`embed_sources`], as it was on
+    /// the build machine. Requires the `embedded-sources` feature. Defaults to
+    /// `None`.
+    ///
+    /// [`embed_sources`]: crate::embed_sources
+    pub source_root: Option<String>,
+    /// The size in bytes of the buffer that source files are read through.
+    /// Defaults to 8 KiB.
+    pub read_buffer_size: usize,
+    /// Whether to strip the indentation that all lines of the code snippet
+    /// have in common. Defaults to `false`.
+    pub trim_indentation: bool,
+    /// Path prefixes to replace in the file paths of each log, e.g. to shorten
+    /// `/home/alice/projects/app/src/main.rs` to `<app>/src/main.rs` by
+    /// mapping `/home/alice/projects/app` to `<app>`. Defaults to none.
+    pub path_remap: BTreeMap<String, String>,
+    /// Whether to resolve symlinks and relative components in the file paths
+    /// of each log. Defaults to `true`.
+    pub canonicalize_paths: bool,
+    /// Whether to make the file paths of each log relative to the directory
+    /// of the crate being run. Defaults to `true`.
+    pub relative_paths: bool,
+    /// The minimum [`Level`] a log needs to have in order to be sent. Defaults
+    /// to [`Level::Trace`].
+    pub level: Level,
+    /// The fraction of logs to send, from `0.0` to `1.0`. Defaults to `1.0`.
+    pub sample_rate: f64,
+    /// How long to wait for each attempt at sending a log before giving up on
+    /// it. Defaults to `None`, which waits indefinitely.
+    #[serde(deserialize_with = "deserialize_millis")]
+    pub timeout: Option<Duration>,
+    /// If set, repeats of the previous message from the same call site within
+    /// this duration are suppressed. Defaults to `None`.
+    #[serde(deserialize_with = "deserialize_millis")]
+    pub dedup_window: Option<Duration>,
+    /// How many more times to try sending a log if the first attempt fails.
+    /// Defaults to 0.
+    pub retries: u32,
+    /// How long the addresses that a host name resolves to are reused for.
+    /// Defaults to 60 seconds, and `None` looks them up on every connection.
+    #[serde(deserialize_with = "deserialize_millis")]
+    pub dns_ttl: Option<Duration>,
+    /// Whether to spread connections across all of the addresses that a host
+    /// name resolves to. Defaults to `false`.
+    pub round_robin: bool,
+    /// The maximum size in bytes of a serialised log, above which its code
+    /// snippet is dropped. Defaults to [`DEFAULT_MAX_PAYLOAD_SIZE`].
+    pub max_payload_size: usize,
+    /// If set, logs whose serialised size in bytes exceeds this get a warning.
+    /// Has to be below `max_payload_size`. Defaults to `None`.
+    pub warn_payload_size: Option<usize>,
+    /// Overrides the `host` and `port` for logs of a given [`Level`]. Defaults
+    /// to none.
+    pub routes: BTreeMap<Level, Destination>,
+    /// Backup servers to fall back to, in order, when the one a log should be
+    /// sent to can't be connected to. Defaults to none.
+    pub failover: Vec<Destination>,
+    /// If set, connections to the gRPC server are made over TLS. Requires the
+    /// `tls` feature. Defaults to `None`.
+    pub tls: Option<TlsConfig>,
+    /// If set, logs are POSTed to this `http://` or `https://` URL instead of
+    /// being sent to the gRPC server. Requires the `http` feature. Defaults to
+    /// `None`.
+    pub http_url: Option<String>,
+    /// If set, logs are sent to this `ws://` or `wss://` URL as binary
+    /// WebSocket messages instead of being sent to the gRPC server. Requires
+    /// the `websocket` feature. Defaults to `None`.
+    pub websocket_url: Option<String>,
+    /// The [`Serializer`] that logs sent to `http_url` or `websocket_url` are
+    /// serialised with. Can't be set from a config file or environment
+    /// variable. Defaults to CBOR.
+    ///
+    /// [`Serializer`]: crate::Serializer
+    #[serde(skip)]
+    pub serializer: SharedSerializer,
+    /// If set, logs are written to stdout or stderr as newline-delimited JSON
+    /// instead of being sent anywhere. Requires the `console` feature. Defaults
+    /// to `None`.
+    pub console: Option<Console>,
+    /// If set, logs that can't be sent because no tokio runtime could be
+    /// created for them are written to this console stream the same way as
+    /// with `console`. Requires the `console` feature. Defaults to `None`.
+    pub runtime_fallback: Option<Console>,
+    /// A bearer token that the server authenticates clients with, which isn't
+    /// sent in cleartext except to loopback addresses. Defaults to `None`.
+    pub auth_token: Option<String>,
+    /// A hex-encoded 256-bit key used to encrypt each log sent to `http_url`
+    /// or `websocket_url` with ChaCha20-Poly1305. Requires the `encryption`
+    /// feature. Defaults to `None`.
+    pub encryption_key: Option<String>,
+    /// A key used to sign each log sent to `http_url` or `websocket_url` with
+    /// HMAC-SHA256. Requires the `hmac` feature. Defaults to `None`.
+    pub hmac_key: Option<String>,
+    /// If set, connections to the gRPC server, as well as to `websocket_url`,
+    /// are made through this SOCKS5 proxy. Requires the `socks` feature.
+    /// Defaults to `None`.
+    pub proxy: Option<ProxyConfig>,
+    /// If set, [`Logger::new`] spawns a background thread that sends the logs
+    /// queued with [`Logger::queue_log`]. Defaults to `None`.
+    ///
+    /// [`Logger::new`]: crate::Logger::new
+    /// [`Logger::queue_log`]: crate::Logger::queue_log
+    pub background: Option<BackgroundConfig>,
+    /// If set, a [`Logger`] stops sending batches and queued logs for a while
+    /// after too many attempts in a row have failed, unlike logs sent through
+    /// associated functions such as [`Logger::log`]. Defaults to `None`.
+    ///
+    /// [`Logger`]: crate::Logger
+    /// [`Logger::log`]: crate::Logger::log
+    pub circuit_breaker: Option<CircuitBreakerConfig>,
+}
+
+/// A gRPC server that logs can be sent to.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+pub struct Destination {
+    /// The host name or IP address of the gRPC server.
+    pub host: String,
+    /// The port of the `host` gRPC server.
+    #[serde(deserialize_with = "deserialize_port")]
+    pub port: String,
+}
+
+/// The options for connecting to the gRPC server over TLS, see
+/// [`LoggerConfig::tls`].
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize)]
+#[serde(default)]
+pub struct TlsConfig {
+    /// The name that the server's certificate is checked against. Defaults to
+    /// the host that's being connected to.
+    pub domain_name: Option<String>,
+    /// The CA certificates to trust. Defaults to the Mozilla root
+    /// certificates.
+    pub ca_cert: Option<Pem>,
+    /// The hex-encoded SHA-256 fingerprint of the server's certificate, with
+    /// or without `:` separators. If set, only that exact certificate is
+    /// accepted, and the CA certificates aren't consulted at all.
+    pub pinned_fingerprint: Option<String>,
+    /// The certificate chain to present to servers that require clients to
+    /// authenticate. Must be set together with `client_key`.
+    pub client_cert: Option<Pem>,
+    /// The private key belonging to `client_cert`.
+    pub client_key: Option<Pem>,
+}
+
+/// The options for connecting through a SOCKS5 proxy, see
+/// [`LoggerConfig::proxy`].
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize)]
+#[serde(default)]
+pub struct ProxyConfig {
+    /// The address of the proxy, as `host:port`.
+    pub address: String,
+    /// The user name to authenticate to the proxy with. Must be set together
+    /// with `password`.
+    pub username: Option<String>,
+    /// The password belonging to `username`.
+    pub password: Option<String>,
+}
+
+/// How many lines of code around the logged line to capture as its code
+/// snippet, see [`LoggerConfig::surround`]. A single number applies to both
+/// sides, both through [`From<u32>`] and in config files, where separate counts
+/// are written as `{ before = 5, after = 1 }`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(from = "SurroundRepr")]
+pub struct Surround {
+    /// The number of lines before the logged line.
+    pub before: u32,
+    /// The number of lines after the logged line.
+    pub after: u32,
+}
+
+impl From<u32> for Surround {
+    fn from(lines: u32) -> Self {
+        Self {
+            before: lines,
+            after: lines,
+        }
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum SurroundRepr {
+    Symmetric(u32),
+    Asymmetric { before: u32, after: u32 },
+}
+
+impl From<SurroundRepr> for Surround {
+    fn from(repr: SurroundRepr) -> Self {
+        match repr {
+            SurroundRepr::Symmetric(lines) => lines.into(),
+            SurroundRepr::Asymmetric { before, after } => Self { before, after },
+        }
+    }
+}
+
+/// PEM-encoded certificates or keys, either read from a file or given
+/// directly. In config files, they're written as `{ path = "client.pem" }` or
+/// `{ pem = "-----BEGIN CERTIFICATE-----..." }` respectively.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Pem {
+    /// The path to a PEM file.
+    Path(PathBuf),
+    /// The contents of a PEM file.
+    Pem(String),
+}
+
+/// The console stream that logs are written to, see [`LoggerConfig::console`].
+/// In config files and `CODECTRL_CONSOLE`, it's written as `"stdout"` or
+/// `"stderr"`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Console {
+    Stdout,
+    Stderr,
+}
+
+/// The options for sending logs from a background thread, see
+/// [`LoggerConfig::background`].
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(default)]
+pub struct BackgroundConfig {
+    /// How many logs can be queued up before the queue is full.
+    pub capacity: usize,
+    /// Whether logs queued while the queue is full are dropped. If `false`,
+    /// queueing a log blocks until there's room for it instead.
+    pub drop_on_full: bool,
+    /// If set, the queue is also full once the serialised logs in it, along
+    /// with the ones being sent, add up to this many bytes, so that a few huge
+    /// logs can't take up as much memory as `capacity` ones. A single log
+    /// larger than this is only queued once nothing else is pending.
+    pub max_bytes: Option<usize>,
+    /// How many connections queued logs are sent over, each from its own
+    /// thread, so that a slow send doesn't hold up every other log. With more
+    /// than one, logs may arrive in a different order than they were queued
+    /// in.
+    pub connections: usize,
+}
+
+/// The options for the circuit breaker, see [`LoggerConfig::circuit_breaker`].
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(default)]
+pub struct CircuitBreakerConfig {
+    /// How many sends in a row have to fail for the circuit to open.
+    pub failures: u32,
+    /// How long the circuit stays open before a log is sent to check whether
+    /// the server is back. Given in milliseconds in config files.
+    #[serde(deserialize_with = "deserialize_required_millis")]
+    pub cooldown: Duration,
+}
+
+impl Default for CircuitBreakerConfig {
+    fn default() -> Self {
+        Self {
+            failures: 5,
+            cooldown: Duration::from_secs(30),
+        }
+    }
+}
+
+impl Default for BackgroundConfig {
+    fn default() -> Self {
+        Self {
+            capacity: 1024,
+            drop_on_full: true,
+            max_bytes: None,
+            connections: 1,
+        }
+    }
+}
+
+impl Default for LoggerConfig {
+    fn default() -> Self {
+        Self {
+            host: "127.0.0.1".into(),
+            port: "3002".into(),
+            surround: 3.into(),
+            capture_stack: true,
+            max_stack_frames: None,
+            skip_crates: Vec::new(),
+            capture_snippet: true,
+            source_root: None,
+            read_buffer_size: 8 * 1024,
+            trim_indentation: false,
+            path_remap: BTreeMap::new(),
+            canonicalize_paths: true,
+            relative_paths: true,
+            level: Level::Trace,
+            sample_rate: 1.0,
+            timeout: None,
+            dedup_window: None,
+            retries: 0,
+            dns_ttl: Some(Duration::from_secs(60)),
+            round_robin: false,
+            max_payload_size: DEFAULT_MAX_PAYLOAD_SIZE,
+            warn_payload_size: None,
+            routes: BTreeMap::new(),
+            failover: Vec::new(),
+            tls: None,
+            http_url: None,
+            websocket_url: None,
+            serializer: SharedSerializer::default(),
+            console: None,
+            runtime_fallback: None,
+            auth_token: None,
+            encryption_key: None,
+            hmac_key: None,
+            proxy: None,
+            background: None,
+            circuit_breaker: None,
+        }
+    }
+}
+
+impl LoggerConfig {
+    /// Creates a config from the default config, overriding any values that
+    /// are set through the following environment variables:
+    ///
+    /// - `CODECTRL_HOST`
+    /// - `CODECTRL_PORT`
+    /// - `CODECTRL_SURROUND`, applied to both sides
+    /// - `CODECTRL_CAPTURE_STACK`, either `true` or `false`
+    /// - `CODECTRL_MAX_STACK_FRAMES`
+    /// - `CODECTRL_SKIP_CRATES`, as a comma-separated list, where empty items
+    ///   are ignored, so that an empty string is an empty list
+    /// - `CODECTRL_CAPTURE_SNIPPET`, either `true` or `false`
+    /// - `CODECTRL_SOURCE_ROOT`
+    /// - `CODECTRL_READ_BUFFER_SIZE`, in bytes
+    /// - `CODECTRL_TRIM_INDENTATION`, either `true` or `false`
+    /// - `CODECTRL_RELATIVE_PATHS`, either `true` or `false`
+    /// - `CODECTRL_LEVEL`, one of `trace`, `debug`, `info`, `warn` or `error`
+    /// - `CODECTRL_SAMPLE_RATE`, from `0.0` to `1.0`
+    /// - `CODECTRL_TIMEOUT`, in milliseconds
+    /// - `CODECTRL_DEDUP_WINDOW`, in milliseconds
+    /// - `CODECTRL_RETRIES`
+    /// - `CODECTRL_DNS_TTL`, in milliseconds
+    /// - `CODECTRL_ROUND_ROBIN`, either `true` or `false`
+    /// - `CODECTRL_MAX_PAYLOAD_SIZE`, in bytes
+    /// - `CODECTRL_WARN_PAYLOAD_SIZE`, in bytes
+    /// - `CODECTRL_HTTP_URL`
+    /// - `CODECTRL_WEBSOCKET_URL`
+    /// - `CODECTRL_CONSOLE`, either `stdout` or `stderr`
+    /// - `CODECTRL_RUNTIME_FALLBACK`, either `stdout` or `stderr`
+    /// - `CODECTRL_AUTH_TOKEN`
+    /// - `CODECTRL_ENCRYPTION_KEY`
+    /// - `CODECTRL_HMAC_KEY`
+    /// - `CODECTRL_PROXY`, the address of the SOCKS5 proxy as `host:port`
+    pub fn from_env() -> Result<Self, ConfigError> {
+        let mut config = Self::default();
+        config.apply_env()?;
+        config.validate()?;
+
+        Ok(config)
+    }
+
+    /// Creates a config from the TOML file at `path`, using the default value
+    /// for any key that's missing from it. The keys are the same as the field
+    /// names, with `timeout`, `dedup_window` and `dns_ttl` given in
+    /// milliseconds:
+    ///
+    /// ```toml
+    /// host = "127.0.0.1"
+    /// port = 3002
+    /// level = "info"
+    /// timeout = 5000
+    ///
+    /// [routes.error]
+    /// host = "10.0.0.2"
+    /// port = 3002
+    ///
+    /// [path_remap]
+    /// "/home/alice/projects/app" = "<app>"
+    ///
+    /// [[failover]]
+    /// host = "10.0.0.3"
+    /// port = 3002
+    ///
+    /// [tls]
+    /// pinned_fingerprint = "9f:86:d0:81:...:0a:08"
+    /// client_cert = { path = "client.pem" }
+    /// client_key = { path = "client.key" }
+    ///
+    /// [proxy]
+    /// address = "10.0.0.1:1080"
+    /// username = "alice"
+    /// password = "hunter2"
+    ///
+    /// [background]
+    /// capacity = 1024
+    /// drop_on_full = true
+    /// max_bytes = 16777216
+    /// connections = 1
+    ///
+    /// [circuit_breaker]
+    /// failures = 5
+    /// cooldown = 30000
+    /// ```
+    ///
+    /// Values set through the environment variables listed in
+    /// [`Self::from_env`] take precedence over the ones in the file, and any
+    /// argument passed into a logging function explicitly takes precedence
+    /// over both.
+    pub fn from_file(path: impl AsRef<Path>) -> Result<Self, ConfigError> {
+        let mut config: Self = toml::from_str(&fs::read_to_string(path)?)?;
+        config.apply_env()?;
+        config.validate()?;
+
+        Ok(config)
+    }
+
+    /// Checks that the values of this config make sense together, so that
+    /// misconfigurations surface at startup rather than on the first log.
+    /// This is done by [`init`] and [`Logger::new`], as well as when loading a
+    /// config through [`Self::from_env`] or [`Self::from_file`].
+    ///
+    /// [`Logger::new`]: crate::Logger::new
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        let invalid = |field: &str, reason: &str| {
+            Err(ConfigError::InvalidValue {
+                field: field.to_string(),
+                reason: reason.to_string(),
+            })
+        };
+
+        validate_destination("host", "port", &self.host, &self.port)?;
+
+        for (level, route) in &self.routes {
+            validate_destination(
+                &format!("routes.{level}.host"),
+                &format!("routes.{level}.port"),
+                &route.host,
+                &route.port,
+            )?;
+        }
+
+        for (index, destination) in self.failover.iter().enumerate() {
+            validate_destination(
+                &format!("failover[{index}].host"),
+                &format!("failover[{index}].port"),
+                &destination.host,
+                &destination.port,
+            )?;
+        }
+
+        if self.surround.before.max(self.surround.after) > MAX_SURROUND {
+            return invalid(
+                "surround",
+                &format!("can't be larger than {MAX_SURROUND} lines"),
+            );
+        }
+
+        if !(0.0..=1.0).contains(&self.sample_rate) {
+            return invalid("sample_rate", "has to be between 0.0 and 1.0");
+        }
+
+        if self.timeout == Some(Duration::ZERO) {
+            return invalid("timeout", "every attempt would time out immediately");
+        }
+
+        if self.max_payload_size == 0 {
+            return invalid("max_payload_size", "no log would fit into 0 bytes");
+        }
+
+        if self
+            .warn_payload_size
+            .is_some_and(|warn_size| warn_size >= self.max_payload_size)
+        {
+            return invalid("warn_payload_size", "has to be below max_payload_size");
+        }
+
+        if self.http_url.is_some() && self.websocket_url.is_some() {
+            return invalid("websocket_url", "can't be set together with http_url");
+        }
+
+        if self.console.is_some()
+            && (self.http_url.is_some() || self.websocket_url.is_some())
+        {
+            return invalid(
+                "console",
+                "can't be set together with http_url or websocket_url",
+            );
+        }
+
+        if let Some(auth_token) = &self.auth_token {
+            // Anything else can't be sent in a header.
+            if auth_token.is_empty()
+                || !auth_token.bytes().all(|byte| byte.is_ascii_graphic())
+            {
+                return invalid("auth_token", "has to be non-empty printable ASCII");
+            }
+        }
+
+        if let Some(encryption_key) = &self.encryption_key {
+            if encryption_key.len() != 64
+                || !encryption_key.bytes().all(|byte| byte.is_ascii_hexdigit())
+            {
+                return invalid("encryption_key", "has to be 64 hex digits");
+            }
+
+            if self.http_url.is_none() && self.websocket_url.is_none() {
+                return invalid(
+                    "encryption_key",
+                    "logs sent to the gRPC server can't be encrypted, so http_url or \
+                     websocket_url has to be set",
+                );
+            }
+        }
+
+        if let Some(hmac_key) = &self.hmac_key {
+            if hmac_key.is_empty() {
+                return invalid("hmac_key", "an empty key can be guessed by anyone");
+            }
+
+            if self.http_url.is_none() && self.websocket_url.is_none() {
+                return invalid(
+                    "hmac_key",
+                    "logs sent to the gRPC server can't be signed, so http_url or \
+                     websocket_url has to be set",
+                );
+            }
+        }
+
+        if self.max_stack_frames == Some(0) {
+            return invalid(
+                "max_stack_frames",
+                "set capture_stack to false to capture no stack frames",
+            );
+        }
+
+        if self.skip_crates.iter().any(String::is_empty) {
+            return invalid("skip_crates", "crate names can't be empty");
+        }
+
+        if self.source_root.as_deref() == Some("") {
+            return invalid("source_root", "can't be empty");
+        }
+
+        if self.read_buffer_size == 0 {
+            return invalid("read_buffer_size", "the buffer can't be empty");
+        }
+
+        if let Some(tls) = &self.tls {
+            if tls.client_cert.is_some() != tls.client_key.is_some() {
+                return invalid(
+                    "tls",
+                    "client_cert and client_key have to be set together",
+                );
+            }
+        }
+
+        if let Some(proxy) = &self.proxy {
+            if proxy.address.is_empty() {
+                return invalid("proxy.address", "address can't be empty");
+            }
+
+            if proxy.username.is_some() != proxy.password.is_some() {
+                return invalid("proxy", "username and password have to be set together");
+            }
+        }
+
+        if let Some(circuit_breaker) = &self.circuit_breaker {
+            if circuit_breaker.failures == 0 {
+                return invalid(
+                    "circuit_breaker.failures",
+                    "the circuit would open without any failures",
+                );
+            }
+        }
+
+        if let Some(background) = &self.background {
+            if background.capacity == 0 {
+                return invalid(
+                    "background.capacity",
+                    "the queue needs room for at least one log",
+                );
+            }
+
+            if background.max_bytes == Some(0) {
+                return invalid("background.max_bytes", "no log would fit into 0 bytes");
+            }
+
+            if background.connections == 0 {
+                return invalid(
+                    "background.connections",
+                    "logs need at least one connection to be sent over",
+                );
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Returns the host and port that logs of the given `level` should be sent
+    /// to, taking [`Self::routes`] into account.
+    pub fn destination(&self, level: Level) -> (&str, &str) {
+        match self.routes.get(&level) {
+            Some(route) => (&route.host, &route.port),
+            None => (&self.host, &self.port),
+        }
+    }
+
+    fn apply_env(&mut self) -> Result<(), ConfigError> {
+        if let Some(host) = env_var::<String>("CODECTRL_HOST")? {
+            if host.is_empty() {
+                return Err(ConfigError::InvalidEnvVar {
+                    name: "CODECTRL_HOST",
+                    value: host,
+                    reason: "host can't be empty".into(),
+                });
+            }
+
+            self.host = host;
+        }
+
+        // Parsed as a `u16` so that invalid ports are caught here rather than when
+        // connecting.
+        if let Some(port) = env_var::<u16>("CODECTRL_PORT")? {
+            self.port = port.to_string();
+        }
+
+        if let Some(surround) = env_var::<u32>("CODECTRL_SURROUND")? {
+            self.surround = surround.into();
+        }
+
+        if let Some(capture_stack) = env_var("CODECTRL_CAPTURE_STACK")? {
+            self.capture_stack = capture_stack;
+        }
+
+        if let Some(max_stack_frames) = env_var("CODECTRL_MAX_STACK_FRAMES")? {
+            self.max_stack_frames = Some(max_stack_frames);
+        }
+
+        if let Some(skip_crates) = env_var::<String>("CODECTRL_SKIP_CRATES")? {
+            self.skip_crates = skip_crates
+                .split(',')
+                .map(str::trim)
+                .filter(|name| !name.is_empty())
+                .map(str::to_string)
+                .collect();
+        }
+
+        if let Some(capture_snippet) = env_var("CODECTRL_CAPTURE_SNIPPET")? {
+            self.capture_snippet = capture_snippet;
+        }
+
+        if let Some(source_root) = env_var("CODECTRL_SOURCE_ROOT")? {
+            self.source_root = Some(source_root);
+        }
+
+        if let Some(read_buffer_size) = env_var("CODECTRL_READ_BUFFER_SIZE")? {
+            self.read_buffer_size = read_buffer_size;
+        }
+
+        if let Some(trim_indentation) = env_var("CODECTRL_TRIM_INDENTATION")? {
+            self.trim_indentation = trim_indentation;
+        }
+
+        if let Some(relative_paths) = env_var("CODECTRL_RELATIVE_PATHS")? {
+            self.relative_paths = relative_paths;
+        }
+
+        if let Some(level) = env_var("CODECTRL_LEVEL")? {
+            self.level = level;
+        }
+
+        if let Some(sample_rate) = env_var("CODECTRL_SAMPLE_RATE")? {
+            self.sample_rate = sample_rate;
+        }
+
+        if let Some(timeout) = env_var("CODECTRL_TIMEOUT")? {
+            self.timeout = Some(Duration::from_millis(timeout));
+        }
+
+        if let Some(dedup_window) = env_var("CODECTRL_DEDUP_WINDOW")? {
+            self.dedup_window = Some(Duration::from_millis(dedup_window));
+        }
+
+        if let Some(retries) = env_var("CODECTRL_RETRIES")? {
+            self.retries = retries;
+        }
+
+        if let Some(dns_ttl) = env_var("CODECTRL_DNS_TTL")? {
+            self.dns_ttl = Some(Duration::from_millis(dns_ttl));
+        }
+
+        if let Some(round_robin) = env_var("CODECTRL_ROUND_ROBIN")? {
+            self.round_robin = round_robin;
+        }
+
+        if let Some(max_payload_size) = env_var("CODECTRL_MAX_PAYLOAD_SIZE")? {
+            self.max_payload_size = max_payload_size;
+        }
+
+        if let Some(warn_payload_size) = env_var("CODECTRL_WARN_PAYLOAD_SIZE")? {
+            self.warn_payload_size = Some(warn_payload_size);
+        }
+
+        if let Some(http_url) = env_var("CODECTRL_HTTP_URL")? {
+            self.http_url = Some(http_url);
+        }
+
+        if let Some(websocket_url) = env_var("CODECTRL_WEBSOCKET_URL")? {
+            self.websocket_url = Some(websocket_url);
+        }
+
+        if let Some(console) = console_env_var("CODECTRL_CONSOLE")? {
+            self.console = Some(console);
+        }
+
+        if let Some(runtime_fallback) = console_env_var("CODECTRL_RUNTIME_FALLBACK")? {
+            self.runtime_fallback = Some(runtime_fallback);
+        }
+
+        if let Some(auth_token) = env_var("CODECTRL_AUTH_TOKEN")? {
+            self.auth_token = Some(auth_token);
+        }
+
+        if let Some(encryption_key) = env_var("CODECTRL_ENCRYPTION_KEY")? {
+            self.encryption_key = Some(encryption_key);
+        }
+
+        if let Some(hmac_key) = env_var("CODECTRL_HMAC_KEY")? {
+            self.hmac_key = Some(hmac_key);
+        }
+
+        // Keeps the credentials of a proxy from the config file, if any.
+        if let Some(address) = env_var("CODECTRL_PROXY")? {
+            self.proxy.get_or_insert_with(ProxyConfig::default).address = address;
+        }
+
+        Ok(())
+    }
+}
+
+fn validate_destination(
+    host_field: &str,
+    port_field: &str,
+    host: &str,
+    port: &str,
+) -> Result<(), ConfigError> {
+    if host.is_empty() {
+        return Err(ConfigError::InvalidValue {
+            field: host_field.to_string(),
+            reason: "host can't be empty".into(),
+        });
+    }
+
+    match port.parse::<u16>() {
+        Ok(port) if port != 0 => Ok(()),
+        _ => Err(ConfigError::InvalidValue {
+            field: port_field.to_string(),
+            reason: format!("{port:?} isn't a valid port"),
+        }),
+    }
+}
+
+fn env_var<T>(name: &'static str) -> Result<Option<T>, ConfigError>
+where
+    T: FromStr,
+    T::Err: Display,
+{
+    match env::var(name) {
+        Ok(value) =>
+            value
+                .parse()
+                .map(Some)
+                .map_err(|e: T::Err| ConfigError::InvalidEnvVar {
+                    name,
+                    reason: e.to_string(),
+                    value,
+                }),
+        Err(env::VarError::NotPresent) => Ok(None),
+        Err(env::VarError::NotUnicode(value)) => Err(ConfigError::InvalidEnvVar {
+            name,
+            value: value.to_string_lossy().into(),
+            reason: "value isn't valid unicode".into(),
+        }),
+    }
+}
+
+fn console_env_var(name: &'static str) -> Result<Option<Console>, ConfigError> {
+    let Some(console) = env_var::<String>(name)? else {
+        return Ok(None);
+    };
+
+    match console.to_ascii_lowercase().as_str() {
+        "stdout" => Ok(Some(Console::Stdout)),
+        "stderr" => Ok(Some(Console::Stderr)),
+        _ => Err(ConfigError::InvalidEnvVar {
+            name,
+            value: console,
+            reason: "has to be either stdout or stderr".into(),
+        }),
+    }
+}
+
+// Allows the port to be written as either a number or a string in config
+// files.
+fn deserialize_port<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<String, D::Error> {
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum Port {
+        Number(u16),
+        String(String),
+    }
+
+    Ok(match Port::deserialize(deserializer)? {
+        Port::Number(port) => port.to_string(),
+        Port::String(port) => port,
+    })
+}
+
+fn deserialize_millis<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<Option<Duration>, D::Error> {
+    Ok(Option::<u64>::deserialize(deserializer)?.map(Duration::from_millis))
+}
+
+fn deserialize_required_millis<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<Duration, D::Error> {
+    Ok(Duration::from_millis(u64::deserialize(deserializer)?))
+}
+
+/// Sets the active [`LoggerConfig`], which is used by [`Logger::log`] and
+/// friends, as well as by [`Logger::start_batch`], for any argument that
+/// isn't passed in explicitly.
+///
+/// [`Logger::log`]: crate::Logger::log
+/// [`Logger::start_batch`]: crate::Logger::start_batch
+///
+/// Returns an error without changing the active config if `config` doesn't
+/// pass [`LoggerConfig::validate`].
+pub fn init(config: LoggerConfig) -> Result<(), ConfigError> {
+    config.validate()?;
+
+    let level = config.level;
+
+    *ACTIVE_CONFIG
+        .write()
+        .unwrap_or_else(|poisoned| poisoned.into_inner()) = Some(Arc::new(config));
+
+    MIN_LEVEL.store(level as u8, Ordering::Relaxed);
+
+    Ok(())
+}
+
+/// Changes the minimum [`Level`] of the active [`LoggerConfig`], taking effect
+/// from the next log onwards. This allows the verbosity of a running program
+/// to be adjusted, e.g. from an admin endpoint, without calling [`init`]
+/// again. The logs of a [`Logger`] created with a config of its own have to be
+/// at or above its own minimum level as well.
+///
+/// [`Logger`]: crate::Logger
+pub fn set_level(level: Level) {
+    active_config();
+
+    if let Some(config) = ACTIVE_CONFIG
+        .write()
+        .unwrap_or_else(|poisoned| poisoned.into_inner())
+        .as_mut()
+    {
+        Arc::make_mut(config).level = level;
+    }
+
+    MIN_LEVEL.store(level as u8, Ordering::Relaxed);
+}
+
+// Returns the minimum level of the active config.
+pub(crate) fn min_level() -> Level {
+    if let Some(level) = Level::from_u8(MIN_LEVEL.load(Ordering::Relaxed)) {
+        return level;
+    }
+
+    let level = active_config().level;
+    MIN_LEVEL.store(level as u8, Ordering::Relaxed);
+
+    level
+}
+
+// Returns the config set by `init`. If `init` was never called, the config is
+// loaded from the environment instead so that `CODECTRL_LEVEL` and friends are
+// still honoured.
+pub(crate) fn active_config() -> Arc<LoggerConfig> {
+    if let Some(config) = &*ACTIVE_CONFIG
+        .read()
+        .unwrap_or_else(|poisoned| poisoned.into_inner())
+    {
+        return Arc::clone(config);
+    }
+
+    let config = LoggerConfig::from_env().unwrap_or_else(|e| {
+        eprintln!("Ignoring codeCTRL environment variables: {e}");
+        LoggerConfig::default()
+    });
+
+    Arc::clone(
+        ACTIVE_CONFIG
+            .write()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+            .get_or_insert(Arc::new(config)),
+    )
+}
@@ -0,0 +1,413 @@
+use crate::{check_response, idempotency_key, LoggerConfig, LoggerError, LoggerResult};
+use codectrl_protobuf_bindings::{data::Log, logs_service::LoggerClient};
+use futures_util::stream;
+use std::{
+    collections::BTreeMap,
+    io, iter,
+    net::SocketAddr,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Mutex, MutexGuard,
+    },
+    time::{Duration, Instant},
+};
+use tokio::net;
+use tonic::{
+    metadata::{Ascii, MetadataValue},
+    transport::{self, Channel, Endpoint},
+    Code, Request, Status,
+};
+
+#[cfg(feature = "tls")]
+use crate::tls::{self, Tls};
+#[cfg(feature = "socks")]
+use crate::{proxy, ProxyConfig};
+
+// How long an address that couldn't be connected to is tried after the others
+// when round-robin is enabled.
+const FAILED_ADDRESS_COOLDOWN: Duration = Duration::from_secs(30);
+
+struct Resolved {
+    addresses: Vec<SocketAddr>,
+    expires_at: Instant,
+}
+
+// Shared by every logger, so that the static logging functions benefit from it
+// too.
+static DNS_CACHE: Mutex<BTreeMap<(String, u16), Resolved>> = Mutex::new(BTreeMap::new());
+static FAILED_ADDRESSES: Mutex<BTreeMap<SocketAddr, Instant>> =
+    Mutex::new(BTreeMap::new());
+static NEXT_ADDRESS: AtomicUsize = AtomicUsize::new(0);
+// The index of the destination that was last connected to, out of the primary
+// destination followed by the failover ones, keyed by the primary destination.
+static LAST_GOOD: Mutex<BTreeMap<(String, String), usize>> = Mutex::new(BTreeMap::new());
+
+/// A connection to a gRPC server, returned by [`connect`]. It can be kept
+/// around and reused for as many logs as needed, e.g. by tools that want to
+/// manage their own connection pooling or health checks.
+///
+/// [`connect`]: crate::connect
+#[derive(Debug, Clone)]
+pub struct Connection {
+    client: LoggerClient<Channel>,
+    // Sent as the `authorization` header of every request.
+    authorization: Option<MetadataValue<Ascii>>,
+}
+
+impl Connection {
+    /// Sends a single log, returning an error if the server didn't confirm
+    /// receiving it. The log carries an idempotency key, see
+    /// [`LoggerConfig::retries`].
+    // TODO: Serialise a borrowed view of the log (a `LogRef<'a>`) instead of
+    // cloning it into the request. The `LoggerClient` generated by the protobuf
+    // bindings only accepts an owned `Log`, so this needs a custom codec for the
+    // `SendLog` call first.
+    pub async fn send_log(&mut self, log: &Log) -> LoggerResult<()> {
+        let mut request = self.request(log.clone());
+
+        if let Some(key) = idempotency_key(log) {
+            let key = key
+                .parse()
+                .map_err(|_| LoggerError::LoggerError(format!("Invalid UUID {key:?}")))?;
+            request.metadata_mut().insert("idempotency-key", key);
+        }
+
+        let response = self.client.send_log(request).await.map_err(status_error)?;
+
+        check_response(response.into_inner())
+    }
+
+    /// Sends several logs in a single request, returning an error if the
+    /// server didn't confirm receiving them.
+    pub async fn send_logs<I>(&mut self, logs: I) -> LoggerResult<()>
+    where
+        I: IntoIterator<Item = Log>,
+        I::IntoIter: Send + 'static,
+    {
+        let request = self.request(stream::iter(logs));
+        let response = self.client.send_logs(request).await.map_err(status_error)?;
+
+        check_response(response.into_inner())
+    }
+
+    fn request<T>(&self, message: T) -> Request<T> {
+        let mut request = Request::new(message);
+
+        if let Some(authorization) = &self.authorization {
+            request
+                .metadata_mut()
+                .insert("authorization", authorization.clone());
+        }
+
+        request
+    }
+
+    /// Returns the underlying client generated by the protobuf bindings, for
+    /// calls that this type doesn't wrap. Requests made through it don't carry
+    /// [`LoggerConfig::auth_token`] unless it's added to them by hand.
+    pub fn into_inner(self) -> LoggerClient<Channel> { self.client }
+}
+
+// Connects to `host` and `port`, falling back to the destinations in
+// `LoggerConfig::failover` in order if that fails. The destination that was
+// last connected to successfully is tried first.
+pub(crate) async fn connect(
+    host: &str,
+    port: &str,
+    config: &LoggerConfig,
+) -> LoggerResult<Connection> {
+    let destinations: Vec<_> =
+        iter::once((host, port))
+            .chain(config.failover.iter().map(|destination| {
+                (destination.host.as_str(), destination.port.as_str())
+            }))
+            .collect();
+
+    let key = (host.to_string(), port.to_string());
+    let last_good = lock(&LAST_GOOD).get(&key).copied().unwrap_or(0);
+
+    let order = iter::once(last_good)
+        .chain((0..destinations.len()).filter(|&index| index != last_good))
+        .filter(|&index| index < destinations.len());
+
+    let mut last_error = None;
+
+    for index in order {
+        let (host, port) = destinations[index];
+
+        match connect_to(host, port, config).await {
+            Ok(client) => {
+                lock(&LAST_GOOD).insert(key, index);
+                return Ok(client);
+            },
+            Err(e) => last_error = Some(e),
+        }
+    }
+
+    Err(last_error.expect("there's always at least one destination"))
+}
+
+// Resolves `host`, which can be either an IP address or a DNS name, and
+// connects to the first of its addresses that accepts the connection. With
+// round-robin enabled, each connection starts from the address after the one
+// the previous connection started from. With a proxy, connecting to `host` is
+// left to the proxy instead.
+async fn connect_to(
+    host: &str,
+    port: &str,
+    config: &LoggerConfig,
+) -> LoggerResult<Connection> {
+    let port: u16 = port.parse().map_err(|_| {
+        LoggerError::LoggerError(format!("Can't connect: Invalid port {port:?}"))
+    })?;
+
+    let authorization = match &config.auth_token {
+        Some(token) => Some(format!("Bearer {token}").parse().map_err(|_| {
+            LoggerError::LoggerError("Invalid auth token".to_string())
+        })?),
+        None => None,
+    };
+
+    #[cfg(feature = "tls")]
+    let tls = match &config.tls {
+        // gRPC servers only speak HTTP/2.
+        Some(tls) => Some(Tls::new(tls, host, &[b"h2"])?),
+        None => None,
+    };
+
+    #[cfg(not(feature = "tls"))]
+    if config.tls.is_some() {
+        return Err(LoggerError::TlsError(
+            "TLS requires the `tls` feature to be enabled".to_string(),
+        ));
+    }
+
+    #[cfg(not(feature = "socks"))]
+    if config.proxy.is_some() {
+        return Err(LoggerError::ProxyError(
+            "SOCKS5 proxies require the `socks` feature to be enabled".to_string(),
+        ));
+    }
+
+    // The proxy resolves the host and picks an address itself, so none of the
+    // address handling below applies.
+    #[cfg(feature = "socks")]
+    if let Some(proxy) = &config.proxy {
+        #[cfg(feature = "tls")]
+        let channel = open_proxied_channel(proxy, host, port, tls.as_ref()).await;
+        #[cfg(not(feature = "tls"))]
+        let channel = open_proxied_channel(proxy, host, port).await;
+
+        return match channel {
+            Ok(channel) => Ok(Connection {
+                client: LoggerClient::new(channel),
+                authorization,
+            }),
+            Err(e) => Err(connect_error(e)),
+        };
+    }
+
+    let mut last_error = None;
+
+    let mut addresses = resolve(host, port, config.dns_ttl).await?;
+
+    if config.round_robin && !addresses.is_empty() {
+        let start = NEXT_ADDRESS.fetch_add(1, Ordering::Relaxed) % addresses.len();
+        addresses.rotate_left(start);
+
+        // Moved to the back rather than skipped, so that there's still something
+        // to try if every address failed recently.
+        let failed = lock(&FAILED_ADDRESSES);
+        addresses.sort_by_key(|address| {
+            failed
+                .get(address)
+                .is_some_and(|failed_at| failed_at.elapsed() < FAILED_ADDRESS_COOLDOWN)
+        });
+    }
+
+    for address in addresses {
+        #[cfg(feature = "tls")]
+        let channel = open_channel(address, tls.as_ref()).await;
+        #[cfg(not(feature = "tls"))]
+        let channel = open_channel(address).await;
+
+        match channel {
+            Ok(channel) => {
+                lock(&FAILED_ADDRESSES).remove(&address);
+                return Ok(Connection {
+                    client: LoggerClient::new(channel),
+                    authorization,
+                });
+            },
+            Err(e) => {
+                lock(&FAILED_ADDRESSES).insert(address, Instant::now());
+                last_error = Some(e);
+            },
+        }
+    }
+
+    // The host may have moved, so don't keep handing out the same addresses.
+    forget(host, port);
+
+    Err(match last_error {
+        Some(e) => connect_error(e),
+        None => LoggerError::LoggerError(format!(
+            "Can't connect: {host} didn't resolve to any addresses"
+        )),
+    })
+}
+
+// A rejected auth token is told apart from other errors reported by the server,
+// as retrying won't help with it.
+fn status_error(status: Status) -> LoggerError {
+    if status.code() == Code::Unauthenticated {
+        return LoggerError::Unauthorized(status.message().to_string());
+    }
+
+    status.into()
+}
+
+// TLS handshake and proxy failures are buried inside the transport error, so
+// they're dug out to make them easier to tell apart from other connection
+// failures.
+fn connect_error(e: transport::Error) -> LoggerError {
+    #[cfg(feature = "tls")]
+    if let Some(reason) = tls::handshake_error(&e) {
+        return LoggerError::TlsError(reason);
+    }
+
+    #[cfg(feature = "socks")]
+    if let Some(reason) = proxy::proxy_error(&e) {
+        return LoggerError::ProxyError(reason);
+    }
+
+    e.into()
+}
+
+#[cfg(feature = "tls")]
+async fn open_channel(
+    address: SocketAddr,
+    tls: Option<&Tls>,
+) -> Result<Channel, transport::Error> {
+    let Some(tls) = tls else {
+        return open_channel_without_tls(address).await;
+    };
+
+    let tls = tls.clone();
+
+    Endpoint::from_shared(format!("https://{address}"))?
+        .connect_with_connector(tower::service_fn(move |_| tls.clone().connect(address)))
+        .await
+}
+
+#[cfg(not(feature = "tls"))]
+async fn open_channel(address: SocketAddr) -> Result<Channel, transport::Error> {
+    open_channel_without_tls(address).await
+}
+
+async fn open_channel_without_tls(
+    address: SocketAddr,
+) -> Result<Channel, transport::Error> {
+    Endpoint::from_shared(format!("http://{address}"))?
+        .connect()
+        .await
+}
+
+#[cfg(all(feature = "socks", feature = "tls"))]
+async fn open_proxied_channel(
+    proxy: &ProxyConfig,
+    host: &str,
+    port: u16,
+    tls: Option<&Tls>,
+) -> Result<Channel, transport::Error> {
+    let Some(tls) = tls else {
+        return open_proxied_channel_without_tls(proxy, host, port).await;
+    };
+
+    let tls = tls.clone();
+    let proxy = proxy.clone();
+    let host = host.to_string();
+
+    Endpoint::from_shared(format!("https://{}", authority(&host, port)))?
+        .connect_with_connector(tower::service_fn(move |_| {
+            let (tls, proxy, host) = (tls.clone(), proxy.clone(), host.clone());
+
+            async move { tls.wrap(proxy::connect(&proxy, &host, port).await?).await }
+        }))
+        .await
+}
+
+#[cfg(all(feature = "socks", not(feature = "tls")))]
+async fn open_proxied_channel(
+    proxy: &ProxyConfig,
+    host: &str,
+    port: u16,
+) -> Result<Channel, transport::Error> {
+    open_proxied_channel_without_tls(proxy, host, port).await
+}
+
+#[cfg(feature = "socks")]
+async fn open_proxied_channel_without_tls(
+    proxy: &ProxyConfig,
+    host: &str,
+    port: u16,
+) -> Result<Channel, transport::Error> {
+    let proxy = proxy.clone();
+    let host = host.to_string();
+
+    Endpoint::from_shared(format!("http://{}", authority(&host, port)))?
+        .connect_with_connector(tower::service_fn(move |_| {
+            let (proxy, host) = (proxy.clone(), host.clone());
+
+            async move { proxy::connect(&proxy, &host, port).await }
+        }))
+        .await
+}
+
+// IPv6 addresses have to be written in brackets in URIs.
+#[cfg(feature = "socks")]
+fn authority(host: &str, port: u16) -> String {
+    if host.contains(':') {
+        format!("[{host}]:{port}")
+    } else {
+        format!("{host}:{port}")
+    }
+}
+
+async fn resolve(
+    host: &str,
+    port: u16,
+    ttl: Option<Duration>,
+) -> io::Result<Vec<SocketAddr>> {
+    let key = (host.to_string(), port);
+
+    if ttl.is_some() {
+        if let Some(resolved) = lock(&DNS_CACHE).get(&key) {
+            if resolved.expires_at > Instant::now() {
+                return Ok(resolved.addresses.clone());
+            }
+        }
+    }
+
+    let addresses: Vec<_> = net::lookup_host((host, port)).await?.collect();
+
+    if let Some(ttl) = ttl {
+        lock(&DNS_CACHE).insert(
+            key,
+            Resolved {
+                addresses: addresses.clone(),
+                expires_at: Instant::now() + ttl,
+            },
+        );
+    }
+
+    Ok(addresses)
+}
+
+fn forget(host: &str, port: u16) { lock(&DNS_CACHE).remove(&(host.to_string(), port)); }
+
+fn lock<T>(mutex: &'static Mutex<T>) -> MutexGuard<'static, T> {
+    mutex
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner())
+}
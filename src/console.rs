@@ -0,0 +1,51 @@
+use crate::{Console, LoggerResult};
+use codectrl_protobuf_bindings::data::Log;
+
+#[cfg(feature = "console")]
+use crate::LoggerError;
+#[cfg(feature = "console")]
+use std::io::{self, Write};
+
+// Writes `logs` to `stream` as newline-delimited JSON, for platforms that
+// collect the console output of containers. The lines are written in one go
+// while holding the lock on the stream, so that they don't interleave with
+// output from other threads.
+#[cfg(feature = "console")]
+pub(crate) fn write_logs<'l>(
+    stream: Console,
+    logs: impl IntoIterator<Item = &'l Log>,
+) -> LoggerResult<()> {
+    let mut lines = Vec::new();
+
+    for log in logs {
+        serde_json::to_writer(&mut lines, log).map_err(|e| {
+            LoggerError::LoggerError(format!("Could not serialise log: {e}"))
+        })?;
+        lines.push(b'\n');
+    }
+
+    match stream {
+        Console::Stdout => io::stdout().lock().write_all(&lines)?,
+        Console::Stderr => io::stderr().lock().write_all(&lines)?,
+    }
+
+    Ok(())
+}
+
+// Decodes a log from a line written by `write_logs`.
+#[cfg(feature = "console")]
+pub(crate) fn decode_line(line: &str) -> LoggerResult<Log> {
+    serde_json::from_str(line)
+        .map_err(|e| LoggerError::LoggerError(format!("Could not deserialise log: {e}")))
+}
+
+#[cfg(not(feature = "console"))]
+pub(crate) fn write_logs<'l>(
+    _stream: Console,
+    _logs: impl IntoIterator<Item = &'l Log>,
+) -> LoggerResult<()> {
+    Err(crate::LoggerError::LoggerError(
+        "Writing logs to the console requires the `console` feature to be enabled"
+            .to_string(),
+    ))
+}
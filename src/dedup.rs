@@ -0,0 +1,53 @@
+use codectrl_protobuf_bindings::data::Log;
+use std::{
+    collections::BTreeMap,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+// The log that started the current run of identical messages at a call site,
+// along with how many repeats of it were suppressed since.
+struct Run {
+    log: Log,
+    started_at: Instant,
+    repeats: u64,
+}
+
+// Keyed by the file name and line number of the call site.
+static RUNS: Mutex<BTreeMap<(String, u32), Run>> = Mutex::new(BTreeMap::new());
+
+// Returns the logs to send in place of `log`. That's nothing if `log` repeats
+// the message of the previous log from the same call site within `window` of
+// the start of the run, and `log` itself otherwise. If `log` ends a run, it's
+// preceded by a summary of the repeats that were suppressed.
+pub(crate) fn deduplicate(log: Log, window: Duration) -> Vec<Log> {
+    let key = (log.file_name.clone(), log.line_number);
+    let mut runs = RUNS.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+    let mut logs = Vec::with_capacity(2);
+
+    if let Some(run) = runs.get_mut(&key) {
+        if run.log.message == log.message && run.started_at.elapsed() < window {
+            run.repeats += 1;
+            return logs;
+        }
+
+        if run.repeats > 0 {
+            let mut summary = run.log.clone();
+            summary.message =
+                format!("{} (repeated {} times)", summary.message, run.repeats);
+            logs.push(summary);
+        }
+    }
+
+    runs.insert(
+        key,
+        Run {
+            log: log.clone(),
+            started_at: Instant::now(),
+            repeats: 0,
+        },
+    );
+    logs.push(log);
+
+    logs
+}
@@ -0,0 +1,57 @@
+use crate::LoggerResult;
+
+#[cfg(feature = "encryption")]
+use crate::LoggerError;
+#[cfg(feature = "encryption")]
+use chacha20poly1305::{
+    aead::{AeadInPlace, KeyInit},
+    ChaCha20Poly1305, Key, Nonce,
+};
+
+// The size of the random nonce that each encrypted payload starts with.
+pub(crate) const NONCE_SIZE: usize = 12;
+
+// How many bytes encrypting a payload adds to it, i.e. the nonce and the
+// 16-byte tag that follows the ciphertext.
+pub(crate) const OVERHEAD: usize = NONCE_SIZE + 16;
+
+// Encrypts `payload` in place with ChaCha20-Poly1305 under the hex-encoded
+// 256-bit `key`, and prepends the nonce that it was encrypted with, which the
+// collector needs to decrypt it. A new random nonce is used for every payload.
+#[cfg(feature = "encryption")]
+pub(crate) fn encrypt(key: &str, payload: &mut Vec<u8>) -> LoggerResult<()> {
+    let cipher = ChaCha20Poly1305::new(Key::from_slice(&parse_key(key)?));
+    let nonce: [u8; NONCE_SIZE] = rand::random();
+
+    cipher
+        .encrypt_in_place(Nonce::from_slice(&nonce), b"", payload)
+        .map_err(|_| LoggerError::LoggerError("Could not encrypt log".to_string()))?;
+    payload.splice(0..0, nonce);
+
+    Ok(())
+}
+
+#[cfg(not(feature = "encryption"))]
+pub(crate) fn encrypt(_key: &str, _payload: &mut Vec<u8>) -> LoggerResult<()> {
+    Err(crate::LoggerError::LoggerError(
+        "Encrypting logs requires the `encryption` feature to be enabled".to_string(),
+    ))
+}
+
+#[cfg(feature = "encryption")]
+fn parse_key(key: &str) -> LoggerResult<[u8; 32]> {
+    let invalid = || LoggerError::LoggerError("Invalid encryption key".to_string());
+
+    if key.len() != 64 {
+        return Err(invalid());
+    }
+
+    let mut bytes = [0; 32];
+
+    for (byte, pair) in bytes.iter_mut().zip(key.as_bytes().chunks(2)) {
+        let pair = std::str::from_utf8(pair).map_err(|_| invalid())?;
+        *byte = u8::from_str_radix(pair, 16).map_err(|_| invalid())?;
+    }
+
+    Ok(bytes)
+}
@@ -0,0 +1,184 @@
+use codectrl_protobuf_bindings::data::{BacktraceData, Log};
+use std::cmp::Ordering;
+
+/// Helpers for working with [`BacktraceData`]. It's generated from the
+/// protobuf definitions in `codectrl-protobuf-bindings`, so traits such as
+/// [`Ord`] can't be implemented for it here.
+pub trait BacktraceDataExt {
+    /// Creates a frame at the given location, leaving any other field empty.
+    /// Prefer this over a struct literal, which stops compiling whenever a
+    /// field is added to [`BacktraceData`].
+    fn new(
+        name: impl Into<String>,
+        file_path: impl Into<String>,
+        line_number: u32,
+        column_number: u32,
+    ) -> Self;
+
+    /// Sets the line of code at the frame's location.
+    fn with_code(self, code: impl Into<String>) -> Self;
+
+    /// Compares two frames by their location, ordering them by file path,
+    /// then line number, then column number. Useful for sorting frames, e.g.
+    /// with `frames.sort_by(BacktraceData::cmp_location)`.
+    fn cmp_location(&self, other: &Self) -> Ordering;
+
+    /// Returns the path of the module that the frame's function is in, taken
+    /// from its symbol name, e.g. `app::server` for `app::server::handle`.
+    /// Closures are attributed to the function they're defined in, and trait
+    /// methods such as `<app::Config as core::fmt::Debug>::fmt` to the module
+    /// of the type they're implemented for. Inherent methods such as
+    /// `app::config::Config::new` are attributed to the module of their type
+    /// as well, which is told apart from a module by starting with an
+    /// uppercase letter, as Rust's naming conventions have it. Empty if the
+    /// name has no module.
+    fn module_path(&self) -> &str;
+
+    /// Returns the name of the crate that the frame's function is in, i.e. the
+    /// first component of [`Self::module_path`].
+    fn crate_name(&self) -> &str;
+}
+
+impl BacktraceDataExt for BacktraceData {
+    fn new(
+        name: impl Into<String>,
+        file_path: impl Into<String>,
+        line_number: u32,
+        column_number: u32,
+    ) -> Self {
+        Self {
+            name: name.into(),
+            file_path: file_path.into(),
+            line_number,
+            column_number,
+            ..Default::default()
+        }
+    }
+
+    fn with_code(mut self, code: impl Into<String>) -> Self {
+        self.code = code.into();
+        self
+    }
+
+    fn cmp_location(&self, other: &Self) -> Ordering {
+        (&self.file_path, self.line_number, self.column_number).cmp(&(
+            &other.file_path,
+            other.line_number,
+            other.column_number,
+        ))
+    }
+
+    fn module_path(&self) -> &str { module_path(&self.name) }
+
+    fn crate_name(&self) -> &str {
+        let module_path = self.module_path();
+
+        module_path
+            .split_once("::")
+            .map_or(module_path, |(crate_name, _)| crate_name)
+    }
+}
+
+/// Helpers for working with [`Log`], which can't have methods of its own for
+/// the same reason as [`BacktraceData`].
+pub trait LogExt {
+    /// Returns the file path, line number and column number that the log was
+    /// created at, taken from the innermost frame of its stack trace, which
+    /// is the only place that holds the column. `None` if the stack trace is
+    /// empty, e.g. because it wasn't captured.
+    fn origin(&self) -> Option<(&str, u32, u32)>;
+}
+
+impl LogExt for Log {
+    fn origin(&self) -> Option<(&str, u32, u32)> {
+        self.stack.last().map(|frame| {
+            (frame.file_path.as_str(), frame.line_number, frame.column_number)
+        })
+    }
+}
+
+fn module_path(name: &str) -> &str {
+    // Trait methods are named `<Type as Trait>::function`.
+    if let Some(qualified) = name.strip_prefix('<') {
+        let self_type = qualified
+            .find(" as ")
+            .or_else(|| qualified.rfind('>'))
+            .map_or(qualified, |end| &qualified[..end]);
+
+        return parent(self_type);
+    }
+
+    let mut path = name;
+
+    // `{{closure}}` with the legacy mangling scheme, `{closure#0}` with v0.
+    while let Some((function, last)) = path.rsplit_once("::") {
+        if !last.starts_with('{') {
+            break;
+        }
+
+        path = function;
+    }
+
+    let path = parent(path);
+
+    // The type that an inherent method is implemented for.
+    match path.rsplit_once("::") {
+        Some((module, last)) if starts_uppercase(last) => module,
+        None if starts_uppercase(path) => "",
+        _ => path,
+    }
+}
+
+fn starts_uppercase(name: &str) -> bool {
+    name.starts_with(|c: char| c.is_ascii_uppercase())
+}
+
+// Drops the last component of `path`, along with the generic arguments of the
+// one before it, e.g. `app::Config` for `app::Config<T>::new`. Generic
+// arguments may contain `::` of their own, which isn't split on.
+fn parent(path: &str) -> &str {
+    let mut depth = 0;
+    let mut end = None;
+
+    for (i, byte) in path.bytes().enumerate() {
+        match bracket(path, i) {
+            1 => depth += 1,
+            -1 => depth -= 1,
+            _ if byte == b':' && depth == 0 && path[i + 1..].starts_with(':') =>
+                end = Some(i),
+            _ => (),
+        }
+    }
+
+    end.map_or("", |end| strip_generics(&path[..end]))
+}
+
+// Drops the generic arguments that `path` ends with, if any.
+fn strip_generics(path: &str) -> &str {
+    if !path.ends_with('>') {
+        return path;
+    }
+
+    let mut depth = 0;
+
+    for i in (0..path.len()).rev() {
+        depth -= bracket(path, i);
+
+        if depth == 0 {
+            return path[..i].trim_end_matches("::");
+        }
+    }
+
+    path
+}
+
+// Returns 1 if the byte at `i` in `path` opens generic arguments, -1 if it
+// closes them, and 0 otherwise. The `>` of `->` in function pointer types
+// doesn't close anything.
+fn bracket(path: &str, i: usize) -> i32 {
+    match path.as_bytes()[i] {
+        b'<' => 1,
+        b'>' if i == 0 || path.as_bytes()[i - 1] != b'-' => -1,
+        _ => 0,
+    }
+}
@@ -0,0 +1,176 @@
+use crate::{LoggerConfig, LoggerError, LoggerResult};
+use codectrl_protobuf_bindings::data::Log;
+
+#[cfg(feature = "http")]
+use crate::{
+    check_payload_size, encode_payload, idempotency_key, refuse_cleartext_auth,
+    signature,
+};
+#[cfg(feature = "http")]
+use hyper::{body, client::conn, header, Body, Request, Response, StatusCode, Uri};
+#[cfg(feature = "http")]
+use tokio::{
+    io::{AsyncRead, AsyncWrite},
+    net::TcpStream,
+};
+
+#[cfg(feature = "http")]
+#[cfg(feature = "tls")]
+use crate::tls;
+
+// POSTs `log` to `url`, serialised by `LoggerConfig::serializer`, for
+// collectors that can only be reached through an HTTP load balancer, over TLS
+// with the options in `LoggerConfig::tls` for `https://` URLs. Any response
+// outside of 2xx counts as the log being rejected, except for 401, which means
+// the auth token was. With `LoggerConfig::encryption_key` set, the
+// body is encrypted, and with `LoggerConfig::hmac_key` set, its signature is
+// sent hex-encoded in the `X-CodeCTRL-Signature` header.
+#[cfg(feature = "http")]
+pub(crate) async fn post_log(
+    url: &str,
+    log: &Log,
+    payload: &mut Vec<u8>,
+    config: &LoggerConfig,
+) -> LoggerResult<()> {
+    let invalid = |reason: &str| {
+        LoggerError::LoggerError(format!("Invalid HTTP URL {url:?}: {reason}"))
+    };
+
+    let uri: Uri = url.parse().map_err(|e| invalid(&format!("{e}")))?;
+
+    let secure = match uri.scheme_str() {
+        Some("http") => false,
+        Some("https") => true,
+        _ => return Err(invalid("Only http:// and https:// URLs are supported")),
+    };
+
+    let authority = uri.authority().ok_or_else(|| invalid("Missing host"))?;
+
+    // They would end up in the `Host` header otherwise.
+    if authority.as_str().contains('@') {
+        return Err(invalid(
+            "Credentials in the URL aren't supported, set auth_token instead",
+        ));
+    }
+
+    // IPv6 addresses are written in brackets in URLs, but not when connecting.
+    let host = authority.host().trim_start_matches('[').trim_end_matches(']');
+    let port = authority.port_u16().unwrap_or(if secure { 443 } else { 80 });
+    let path = uri.path_and_query().map_or("/", |path| path.as_str());
+
+    refuse_cleartext_auth(host, secure, config)?;
+
+    encode_payload(
+        log,
+        &config.serializer,
+        config.encryption_key.as_deref(),
+        payload,
+    )?;
+    check_payload_size(payload.len(), config.max_payload_size)?;
+
+    let content_type = match config.encryption_key {
+        Some(_) => "application/octet-stream",
+        None => config.serializer.content_type(),
+    };
+
+    let mut request = Request::post(path)
+        .header(header::HOST, authority.as_str())
+        .header(header::CONTENT_TYPE, content_type);
+
+    if let Some(key) = idempotency_key(log) {
+        request = request.header("Idempotency-Key", key);
+    }
+
+    if let Some(token) = &config.auth_token {
+        request = request.header(header::AUTHORIZATION, format!("Bearer {token}"));
+    }
+
+    if let Some(key) = &config.hmac_key {
+        let signature: String = signature::sign(key, payload)?
+            .iter()
+            .map(|byte| format!("{byte:02x}"))
+            .collect();
+
+        request = request.header("X-CodeCTRL-Signature", signature);
+    }
+
+    // The body has to own its bytes, so they're copied out at their exact size,
+    // leaving `payload` with the capacity it grew to for the next log.
+    let request = request
+        .body(Body::from(payload.clone()))
+        .map_err(|e| LoggerError::LoggerError(format!("Invalid HTTP request: {e}")))?;
+
+    let stream = TcpStream::connect((host, port)).await?;
+    let response = if secure {
+        send(wrap_tls(stream, host, config).await?, request).await?
+    } else {
+        send(stream, request).await?
+    };
+    let status = response.status();
+
+    if status.is_success() {
+        return Ok(());
+    }
+
+    let body = body::to_bytes(response.into_body()).await?;
+    let body = String::from_utf8_lossy(&body).into_owned();
+
+    if status == StatusCode::UNAUTHORIZED {
+        return Err(LoggerError::Unauthorized(body));
+    }
+
+    Err(LoggerError::Rejected {
+        status: status.as_u16(),
+        body,
+    })
+}
+
+// Sends `request` over a connection of its own.
+#[cfg(feature = "http")]
+async fn send<S>(stream: S, request: Request<Body>) -> LoggerResult<Response<Body>>
+where
+    S: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+{
+    let (mut sender, connection) = conn::handshake(stream).await?;
+
+    // Drives the connection until the response has been read.
+    tokio::spawn(async move {
+        let _ = connection.await;
+    });
+
+    Ok(sender.send_request(request).await?)
+}
+
+#[cfg(feature = "http")]
+#[cfg(feature = "tls")]
+async fn wrap_tls(
+    stream: TcpStream,
+    host: &str,
+    config: &LoggerConfig,
+) -> LoggerResult<tokio_rustls::client::TlsStream<TcpStream>> {
+    tls::wrap_stream(stream, host, config).await
+}
+
+#[cfg(feature = "http")]
+#[cfg(not(feature = "tls"))]
+async fn wrap_tls(
+    _stream: TcpStream,
+    _host: &str,
+    _config: &LoggerConfig,
+) -> LoggerResult<TcpStream> {
+    Err(LoggerError::TlsError(
+        "https:// URLs require the `tls` feature to be enabled".to_string(),
+    ))
+}
+
+#[cfg(not(feature = "http"))]
+pub(crate) async fn post_log(
+    _url: &str,
+    _log: &Log,
+    _payload: &mut Vec<u8>,
+    _config: &LoggerConfig,
+) -> LoggerResult<()> {
+    Err(LoggerError::LoggerError(
+        "Sending logs over HTTP requires the `http` feature to be enabled".to_string(),
+    ))
+}
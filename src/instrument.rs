@@ -0,0 +1,130 @@
+use crate::{create_log_with_message, Level, Logger};
+use std::{fmt::Debug, time::Instant};
+
+/// Logs the entry into a function along with its arguments, then runs `body`
+/// and logs what it returned along with how long it took, e.g.:
+///
+/// ```no_run
+/// fn add(a: i32, b: i32) -> i32 {
+///     codectrl::instrument!(add(a, b) {
+///         a + b
+///     })
+/// }
+/// ```
+///
+/// The arguments have to implement [`Debug`], as does the return value. Both
+/// logs of a call share a randomly generated call ID, so that they can be
+/// paired up even when calls overlap. A host and port to send the logs to can
+/// be given before the function name, as in
+/// `codectrl::instrument!(Some("10.0.0.2"), Some("3002"); add(a, b) { .. })`,
+/// otherwise they're taken from the config set by [`init`](crate::init).
+///
+/// `body` runs in a closure, so a `return` in it returns from `body` rather
+/// than from the surrounding function, which keeps the exit from going
+/// unlogged. For the same reason, `body` can't `.await` unless it's marked
+/// `async`, as in `codectrl::instrument!(fetch(url) async { .. }).await`, in
+/// which case it runs in an `async move` block instead.
+///
+/// The arguments and the return value are only formatted if the log they're
+/// part of passes the configured level and sample rate. The logs are sent the
+/// same way as by [`Logger::log`], and any error in sending them is ignored, so
+/// that instrumenting a function never changes what it returns.
+#[macro_export]
+macro_rules! instrument {
+    (@enter $host:expr, $port:expr; $name:ident($($arg:ident),*)) => {
+        $crate::InstrumentedCall::enter(
+            ::std::stringify!($name),
+            || {
+                let args: &[String] = &[
+                    $(::std::format!("{} = {:?}", ::std::stringify!($arg), &$arg)),*
+                ];
+
+                args.join(", ")
+            },
+            $host,
+            $port,
+        )
+    };
+    ($host:expr, $port:expr; $name:ident($($arg:ident),* $(,)?) async $body:block) => {
+        async {
+            let call = $crate::instrument!(@enter $host, $port; $name($($arg),*));
+            let ret = async move $body.await;
+            call.exit(&ret);
+
+            ret
+        }
+    };
+    ($host:expr, $port:expr; $name:ident($($arg:ident),* $(,)?) $body:block) => {{
+        let call = $crate::instrument!(@enter $host, $port; $name($($arg),*));
+        #[allow(clippy::redundant_closure_call)]
+        let ret = (|| $body)();
+        call.exit(&ret);
+
+        ret
+    }};
+    ($name:ident($($arg:ident),* $(,)?) async $body:block) => {
+        $crate::instrument!(None, None; $name($($arg),*) async $body)
+    };
+    ($name:ident($($arg:ident),* $(,)?) $body:block) => {
+        $crate::instrument!(None, None; $name($($arg),*) $body)
+    };
+}
+
+/// A call to a function instrumented with [`instrument!`], which logs its
+/// exit through [`Self::exit`]. Only meant to be used by that macro.
+#[doc(hidden)]
+pub struct InstrumentedCall<'a> {
+    name: &'static str,
+    id: u32,
+    started_at: Instant,
+    host: Option<&'a str>,
+    port: Option<&'a str>,
+}
+
+impl<'a> InstrumentedCall<'a> {
+    pub fn enter(
+        name: &'static str,
+        args: impl FnOnce() -> String,
+        host: Option<&'a str>,
+        port: Option<&'a str>,
+    ) -> Self {
+        let call = Self {
+            name,
+            id: rand::random(),
+            started_at: Instant::now(),
+            host,
+            port,
+        };
+
+        call.log(|| format!("-> {name}({})", args()));
+
+        call
+    }
+
+    pub fn exit<T: Debug>(self, ret: &T) {
+        let elapsed = self.started_at.elapsed();
+
+        self.log(|| format!("<- {} returned {ret:?} after {elapsed:?}", self.name));
+    }
+
+    // `message` is only called once the log is known to pass the filters.
+    fn log(&self, message: impl FnOnce() -> String) {
+        let _ = Logger::log_with_level(
+            Level::Info,
+            |surround, config| {
+                create_log_with_message(
+                    format!("[call {:08x}] {}", self.id, message()),
+                    "text/plain".into(),
+                    surround,
+                    None,
+                    None,
+                    config,
+                )
+            },
+            None,
+            self.host,
+            self.port,
+            None,
+        );
+    }
+}
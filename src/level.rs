@@ -0,0 +1,82 @@
+use serde::{Deserialize, Serialize};
+use std::{fmt, str::FromStr};
+
+/// The severity of a log. Levels are ordered from least to most severe, so
+/// that they can be compared against the minimum level set in
+/// [`LoggerConfig`].
+///
+/// [`LoggerConfig`]: crate::LoggerConfig
+#[derive(
+    Debug,
+    Clone,
+    Copy,
+    PartialEq,
+    Eq,
+    PartialOrd,
+    Ord,
+    Hash,
+    Default,
+    Serialize,
+    Deserialize,
+)]
+#[serde(rename_all = "lowercase")]
+pub enum Level {
+    Trace,
+    Debug,
+    #[default]
+    Info,
+    Warn,
+    Error,
+}
+
+impl Level {
+    pub(crate) fn from_u8(level: u8) -> Option<Self> {
+        [
+            Self::Trace,
+            Self::Debug,
+            Self::Info,
+            Self::Warn,
+            Self::Error,
+        ]
+        .get(level as usize)
+        .copied()
+    }
+}
+
+// TODO: Colour the label by severity (e.g. red for `Error`, yellow for `Warn`,
+// blue for `Info`) when writing to a terminal, with a way of turning it off for
+// log scrapers. There's no dry-run or terminal output that prints levels yet,
+// only the gRPC server displays them, so this has to wait until there is one.
+impl fmt::Display for Level {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let level = match self {
+            Self::Trace => "TRACE",
+            Self::Debug => "DEBUG",
+            Self::Info => "INFO",
+            Self::Warn => "WARN",
+            Self::Error => "ERROR",
+        };
+
+        write!(f, "{level}")
+    }
+}
+
+/// The error returned when parsing a [`Level`] from a string fails.
+#[derive(thiserror::Error, Debug, Clone, PartialEq, Eq)]
+#[error("Unknown log level: {0:?}")]
+pub struct ParseLevelError(String);
+
+impl FromStr for Level {
+    type Err = ParseLevelError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "trace" => Ok(Self::Trace),
+            "debug" => Ok(Self::Debug),
+            "info" => Ok(Self::Info),
+            "warn" | "warning" => Ok(Self::Warn),
+            "error" => Ok(Self::Error),
+            _ => Err(ParseLevelError(s.into())),
+        }
+    }
+}
@@ -2,28 +2,85 @@
 
 // TODO(important): Replace tokio runtime handlers with tasks and LocalSet.
 
+mod background;
+mod breaker;
+mod call_site;
+mod callbacks;
+mod config;
+mod connection;
+mod console;
+mod dedup;
+#[cfg(any(feature = "http", feature = "websocket"))]
+mod encryption;
+mod frame;
+mod http;
+mod instrument;
+mod level;
+#[cfg(feature = "socks")]
+mod proxy;
+mod reentrancy;
+mod serializer;
+#[cfg(any(feature = "http", feature = "websocket"))]
+mod signature;
+mod sources;
+mod stats;
 #[cfg(test)]
 mod tests;
-
+#[cfg(feature = "tls")]
+mod tls;
+mod websocket;
+
+#[doc(hidden)]
+pub use call_site::CallSite;
+pub use config::{
+    init, set_level, BackgroundConfig, CircuitBreakerConfig, ConfigError, Console,
+    Destination, LoggerConfig, Pem, ProxyConfig, Surround, TlsConfig,
+};
+pub use connection::Connection;
+pub use frame::{BacktraceDataExt, LogExt};
+#[doc(hidden)]
+pub use instrument::InstrumentedCall;
+pub use level::{Level, ParseLevelError};
+pub use serializer::{Serializer, SharedSerializer};
+#[cfg(feature = "embedded-sources")]
+pub use sources::embed_sources;
+pub use stats::{CircuitState, Flushed, LogOutcome, Stats};
+
+use background::Background;
 use backtrace::Backtrace;
+use breaker::CircuitBreaker;
+use callbacks::Callbacks;
 use codectrl_protobuf_bindings::{
     data::{BacktraceData, Log},
-    logs_service::{LoggerClient, RequestResult, RequestStatus},
+    logs_service::{RequestResult, RequestStatus},
 };
-use futures_util::stream;
 use hashbag::HashBag;
+use prost::Message;
 use serde::{Deserialize, Serialize};
+use stats::Counters;
 use std::{
     cell::RefCell,
     collections::{BTreeMap, VecDeque},
     env,
-    fmt::Debug,
+    error::Error,
+    fmt::{self, Debug},
     fs,
-    fs::File,
-    io::{self, prelude::*, BufReader},
+    future::Future,
+    io::{self, prelude::*},
+    num::ParseIntError,
+    panic::{self, AssertUnwindSafe},
+    path::{Path, PathBuf},
+    str::Utf8Error,
+    string::FromUtf8Error,
+    sync::{Arc, OnceLock},
+    thread,
+    time::{Duration, SystemTimeError},
 };
-use tokio::runtime::{Handle, Runtime};
-use tonic::Request;
+use tokio::{
+    runtime::{Handle, Runtime},
+    task::JoinHandle,
+};
+use uuid::Uuid;
 
 /// The Error type used by [`Logger`] and [`LogBatch`] whenever something can
 /// potentially fail.
@@ -40,15 +97,59 @@ pub enum LoggerError {
     /// error or a file read error.
     #[error("IO error occurred: {0}")]
     IOError(#[from] io::Error),
+    /// An attempt at sending a log took longer than the configured timeout.
+    #[error("Timed out while sending log: {0}")]
+    Timeout(#[from] tokio::time::error::Elapsed),
     /// A possible error returned by the gRPC server itself.
     #[error("gRPC server reported an error: status code {status_code}: {message} ")]
     LogServerError {
         message: String,
         status_code: String,
     },
+    /// TLS couldn't be set up, or the TLS handshake with the server failed,
+    /// e.g. because either side's certificate was rejected.
+    #[error("TLS error: {0}")]
+    TlsError(String),
+    /// The SOCKS5 proxy couldn't be connected to, or refused to connect to the
+    /// server, see [`LoggerConfig::proxy`].
+    #[error("Proxy error: {0}")]
+    ProxyError(String),
     /// An error generated by either [`Logger`] or [`LogBatch`].
     #[error("This logger encountered an error: {0}")]
     LoggerError(String),
+    /// A [`Log`] was still larger than the maximum payload size after its code
+    /// snippet was dropped.
+    ///
+    /// [`Log`]: codectrl_protobuf_bindings::data::Log
+    #[error("Log of {size} bytes exceeds the maximum payload size of {max_size} bytes")]
+    PayloadTooLarge { size: usize, max_size: usize },
+    /// The HTTP collector set in [`LoggerConfig::http_url`] responded with a
+    /// status code outside of 2xx, or the server set in
+    /// [`LoggerConfig::websocket_url`] refused the WebSocket handshake.
+    #[error("Server rejected the log: status code {status}: {body}")]
+    Rejected { status: u16, body: String },
+    /// The server rejected [`LoggerConfig::auth_token`], or requires one
+    /// while none is set. Holds the reason given by the server, if any.
+    #[error("Server rejected the auth token: {0}")]
+    Unauthorized(String),
+    /// An error that has been generated by Hyper while POSTing a log to the
+    /// HTTP collector.
+    #[cfg(feature = "http")]
+    #[error("Hyper reported an error during transport: {0}")]
+    HttpError(#[from] hyper::Error),
+    /// An error that has been generated by tungstenite while sending logs
+    /// over WebSocket.
+    #[cfg(feature = "websocket")]
+    #[error("WebSocket error: {0}")]
+    WebSocketError(#[from] tokio_tungstenite::tungstenite::Error),
+    /// The config passed to [`Logger::new`] didn't pass
+    /// [`LoggerConfig::validate`].
+    #[error("Invalid config: {0}")]
+    ConfigError(#[from] ConfigError),
+    /// The log wasn't sent because the circuit breaker is open, see
+    /// [`LoggerConfig::circuit_breaker`].
+    #[error("Not sending log while the circuit breaker is open")]
+    CircuitOpen,
     /// Any other error with unknown origins.
     #[error("An unknown error occured: {0}")]
     Other(#[from] anyhow::Error),
@@ -65,54 +166,163 @@ impl From<RequestResult> for LoggerError {
 
 type LoggerResult<T> = Result<T, LoggerError>;
 
+/// The default maximum size of a serialised [`Log`], matching the default
+/// message size limit of tonic-based gRPC servers.
+///
+/// [`Log`]: codectrl_protobuf_bindings::data::Log
+pub const DEFAULT_MAX_PAYLOAD_SIZE: usize = 4 * 1024 * 1024;
+
+// TODO: Let logs carry a small binary attachment along with its content type,
+// capped in size before sending, with an `AttachmentTruncated` warning for
+// attachments over the cap. Blocked on `Log` gaining `attachment` and
+// `attachment_type` fields in the protobuf bindings.
+//
+// Only constructed on some targets and build profiles.
+#[allow(dead_code)]
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
 enum Warning {
     CompiledWithoutDebugInfo,
     NoColumnNumberWindows,
+    SnippetDropped,
+    FrameSkipped,
+    CodeUnreadable,
+    EmptyBacktrace,
+    NoColumnNumber,
+    // Holds how many of the outer frames were left out, not counting those
+    // that would have been left out anyway, e.g. those of the standard library.
+    StackTruncated(usize),
+    // Holds the size of the log in bytes, see `LoggerConfig::warn_payload_size`.
+    LargePayload(usize),
 }
 
-impl ToString for Warning {
-    fn to_string(&self) -> String {
-        match self {
+impl fmt::Display for Warning {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let warning = match self {
             Self::CompiledWithoutDebugInfo =>
                 "File was compiled without debug info, meaning information was lost",
             Self::NoColumnNumberWindows =>
                 "File was compiled on Windows, which does not always report column \
                  numbers",
-        }
-        .into()
+            Self::SnippetDropped =>
+                "Log exceeded the maximum payload size, so the code snippet was dropped",
+            Self::FrameSkipped =>
+                "Some stack frames couldn't be captured and were left out of the stack \
+                 trace",
+            Self::CodeUnreadable =>
+                "The source files of some stack frames couldn't be read, so their code \
+                 is missing",
+            Self::NoColumnNumber =>
+                "Some stack frames have no column number, so their column is reported \
+                 as 0",
+            Self::EmptyBacktrace =>
+                "No stack frames could be captured, so the file name, line number and \
+                 code snippet are missing",
+            Self::StackTruncated(elided) =>
+                return write!(
+                    f,
+                    "The stack trace was cut off at max_stack_frames, leaving out \
+                     {elided} outer frames"
+                ),
+            Self::LargePayload(size) =>
+                return write!(
+                    f,
+                    "Log of {size} bytes exceeds warn_payload_size, so it may contain \
+                     more than was meant to be logged"
+                ),
+        };
+
+        write!(f, "{warning}")
     }
 }
 
 fn create_log<T: Debug>(
     message: T,
-    surround: Option<u32>,
+    surround: Surround,
+    function_name: Option<&str>,
+    function_name_occurences: Option<&HashBag<&'static str>>,
+    config: &LoggerConfig,
+) -> Log {
+    create_log_with_message(
+        format!("{:#?}", &message),
+        std::any::type_name::<T>().to_string(),
+        surround,
+        function_name,
+        function_name_occurences,
+        config,
+    )
+}
+
+fn create_log_with_message(
+    message: String,
+    message_type: String,
+    surround: Surround,
+    function_name: Option<&str>,
+    function_name_occurences: Option<&HashBag<&'static str>>,
+    config: &LoggerConfig,
+) -> Log {
+    create_log_at(
+        message,
+        message_type,
+        surround,
+        function_name,
+        function_name_occurences,
+        None,
+        config,
+    )
+}
+
+// Like `create_log_with_message`, but with the file name and line number taken
+// from `call_site` if given, instead of from the backtrace.
+fn create_log_at(
+    message: String,
+    message_type: String,
+    surround: Surround,
     function_name: Option<&str>,
     function_name_occurences: Option<&HashBag<&'static str>>,
+    call_site: Option<&CallSite>,
+    config: &LoggerConfig,
 ) -> Log {
     let function_name = function_name.unwrap_or_default();
 
+    // `Log` already implements `Default` through prost, so only the fields
+    // that aren't empty need to be set.
+    //
+    // TODO: Stamp every log with more about where and how it was created, once
+    // `Log` gains fields for it in the protobuf bindings:
+    //
+    // - `service` and `environment`, from the config, defaulting to
+    //   `CODECTRL_SERVICE` and `CODECTRL_ENV`.
+    // - The wall-clock time it was created at, for display, and its monotonic
+    //   offset from process start, measured with an `Instant`, so that logs of
+    //   one process run can be ordered even when the wall clock jumps.
+    // - `snippet_hash`, a short hash of the code snippet behind a feature that
+    //   pulls in e.g. `blake3`, so that collectors can tell when the code at a
+    //   file and line changed between deployments.
+    // - `rustc_version`, captured by a build script into `CODECTRL_RUSTC` and
+    //   read with `option_env!`.
+    // - Whether the binary was built with `debug_assertions`, which release
+    //   builds can only be told apart by through the `CompiledWithoutDebugInfo`
+    //   warning until then.
     let mut log = Log {
-        uuid: "".to_string(),
-        stack: Vec::new(),
-        line_number: 0,
-        file_name: String::new(),
-        code_snippet: BTreeMap::new(),
-        message: format!("{:#?}", &message),
-        message_type: std::any::type_name::<T>().to_string(),
-        address: String::new(),
-        warnings: Vec::new(),
+        uuid: Uuid::new_v4().to_string(),
+        message,
+        message_type,
         language: "Rust".into(),
+        ..Default::default()
     };
 
+    // The call site covers the file path and line number, so the warning
+    // would only be noise.
     #[cfg(not(debug_assertions))]
-    eprintln!(
-        "Unfortunately, using this function without debug_assertions enabled will \
-         produce limited information. The stack trace, file path and line number will \
-         be missing from the final message that is sent to the server. Please consider \
-         guarding this function using #[cfg(debug_assertions)] so that this message \
-         does not re-appear."
-    );
+    if call_site.is_none() {
+        eprintln!(
+            "Unfortunately, using this function without debug_assertions enabled will \
+             produce limited information. The stack trace, file path and line number \
+             will be missing from the final message that is sent to the server. Please \
+             consider guarding this function using #[cfg(debug_assertions)] so that \
+             this message does not re-appear."
+        );
+    }
 
     #[cfg(not(debug_assertions))]
     log.warnings
@@ -122,36 +332,516 @@ fn create_log<T: Debug>(
     log.warnings
         .push(Warning::NoColumnNumberWindows.to_string());
 
-    let surround = surround.unwrap_or(3);
+    if config.capture_stack {
+        Logger::get_stack_trace(&mut log, config);
 
-    Logger::get_stack_trace(&mut log);
+        // Happens e.g. in stripped binaries, and would otherwise leave the missing
+        // location unexplained.
+        if log.stack.is_empty() && call_site.is_none() {
+            log.warnings.push(Warning::EmptyBacktrace.to_string());
+        }
+    }
+
+    if let Some(call_site) = call_site {
+        let file_path = call_site.file_path(config);
+        let is_call_site = |frame: &BacktraceData| {
+            frame.file_path == file_path && frame.line_number == call_site.line
+        };
+
+        if !log.stack.last().is_some_and(is_call_site) {
+            let code = if config.capture_snippet && sources::exists(&file_path, config)
+            {
+                Logger::get_code(&file_path, call_site.line, config).unwrap_or_default()
+            } else {
+                String::new()
+            };
+
+            log.stack.push(
+                BacktraceData::new(
+                    call_site.module_path.to_string(),
+                    file_path,
+                    call_site.line,
+                    call_site.column,
+                )
+                .with_code(code),
+            );
+        }
+    }
 
     if let Some(last) = log.stack.last() {
         log.line_number = last.line_number;
 
-        log.code_snippet = Logger::get_code_snippet(
-            &last.file_path,
-            &mut log.line_number,
-            surround,
-            function_name,
-            function_name_occurences,
-        );
+        // Only frames that the code was read for are captured, except for the
+        // frame added for the call site of `log!`, whose file may be missing.
+        if config.capture_snippet && sources::exists(&last.file_path, config) {
+            log.code_snippet = Logger::get_code_snippet(
+                &last.file_path,
+                &mut log.line_number,
+                surround,
+                function_name,
+                function_name_occurences,
+                config,
+            );
+        }
 
+        // The frame keeps its own copy, as both end up in the serialised log.
         log.file_name = last.file_path.clone();
     }
 
+    if config.trim_indentation {
+        trim_indentation(&mut log);
+    }
+
+    // Only done once the code has been read, as that needs the real paths.
+    if !config.path_remap.is_empty() {
+        for frame in log.stack.iter_mut() {
+            frame.file_path = remap_path(&frame.file_path, &config.path_remap);
+        }
+
+        log.file_name = remap_path(&log.file_name, &config.path_remap);
+    }
+
+    if let Some(base_dir) = config.relative_paths.then(base_dir).flatten() {
+        for frame in log.stack.iter_mut() {
+            frame.file_path = strip_base_dir(&frame.file_path, base_dir);
+        }
+
+        log.file_name = strip_base_dir(&log.file_name, base_dir);
+    }
+
     log
 }
 
+// The directory that file paths are made relative to, see
+// `LoggerConfig::relative_paths`. Only looked up once, as the current directory
+// may change while the program runs.
+fn base_dir() -> Option<&'static str> {
+    static BASE_DIR: OnceLock<Option<String>> = OnceLock::new();
+
+    BASE_DIR
+        .get_or_init(|| {
+            let dir = env::var_os("CARGO_MANIFEST_DIR")
+                .map(PathBuf::from)
+                .or_else(|| env::current_dir().ok())?;
+            let dir = fs::canonicalize(&dir).unwrap_or(dir);
+            let dir = dir.to_string_lossy().into_owned();
+
+            #[cfg(target_os = "windows")]
+            let dir = normalize_path(&dir);
+
+            Some(dir)
+        })
+        .as_deref()
+}
+
+// Only the first line of a file can start with a byte order mark, but it
+// shouldn't end up in the code that's sent to the server.
+fn strip_bom(line: &str) -> &str { line.strip_prefix('\u{FEFF}').unwrap_or(line) }
+
+// Makes `path` relative to `base_dir` if it's inside of it.
+fn strip_base_dir(path: &str, base_dir: &str) -> String {
+    path.strip_prefix(base_dir)
+        .and_then(|relative| relative.strip_prefix('/'))
+        .filter(|relative| !relative.is_empty())
+        .unwrap_or(path)
+        .to_string()
+}
+
+// Replaces the longest prefix of `path` found in `remap` with what it maps to.
+fn remap_path(path: &str, remap: &BTreeMap<String, String>) -> String {
+    remap
+        .iter()
+        .filter(|(prefix, _)| path.starts_with(prefix.as_str()))
+        .max_by_key(|(prefix, _)| prefix.len())
+        .map_or_else(
+            || path.to_string(),
+            |(prefix, replacement)| format!("{replacement}{}", &path[prefix.len()..]),
+        )
+}
+
+// Strips the indentation that all lines of the code snippet have in common,
+// keeping their relative indentation, and shifts the column of the logged line
+// to match.
+fn trim_indentation(log: &mut Log) {
+    let indentation = log
+        .code_snippet
+        .values()
+        .filter(|line| !line.trim().is_empty())
+        .map(|line| line.chars().take_while(|c| c.is_whitespace()).count())
+        .min()
+        .unwrap_or(0);
+
+    if indentation == 0 {
+        return;
+    }
+
+    for line in log.code_snippet.values_mut() {
+        if line.trim().is_empty() {
+            line.clear();
+        } else {
+            *line = line.chars().skip(indentation).collect();
+        }
+    }
+
+    if let Some(last) = log.stack.last_mut() {
+        if last.column_number > 0 {
+            last.column_number =
+                last.column_number.saturating_sub(indentation as u32).max(1);
+        }
+    }
+}
+
+// Formats `error` followed by each of its sources on a separate line, in the
+// same style as `anyhow`, so that the root cause isn't lost.
+fn format_error_chain<E: Error + ?Sized>(error: &E) -> String {
+    format_sources(error.to_string(), error.source())
+}
+
+fn format_sources(
+    mut message: String,
+    mut source: Option<&(dyn Error + 'static)>,
+) -> String {
+    if source.is_some() {
+        message.push_str("\n\nCaused by:");
+    }
+
+    let mut index = 0;
+
+    while let Some(error) = source {
+        message.push_str(&format!("\n    {index}: {}", describe_error(error)));
+        source = error.source();
+        index += 1;
+    }
+
+    message
+}
+
+// Formats `error` along with the details that its `Display` output leaves out,
+// for the error types of the standard library that are most commonly logged.
+// Other types are formatted as they are.
+fn describe_error(error: &(dyn Error + 'static)) -> String {
+    let details = if let Some(e) = error.downcast_ref::<io::Error>() {
+        // The OS error code, if any, is already part of the message.
+        Some(format!("kind: {:?}", e.kind()))
+    } else if let Some(e) = error.downcast_ref::<ParseIntError>() {
+        Some(format!("kind: {:?}", e.kind()))
+    } else if let Some(e) = error.downcast_ref::<Utf8Error>() {
+        Some(utf8_error_details(e))
+    } else if let Some(e) = error.downcast_ref::<FromUtf8Error>() {
+        Some(utf8_error_details(&e.utf8_error()))
+    } else {
+        error
+            .downcast_ref::<SystemTimeError>()
+            .map(|e| format!("ahead by: {:?}", e.duration()))
+    };
+
+    match details {
+        Some(details) => format!("{error} ({details})"),
+        None => error.to_string(),
+    }
+}
+
+fn utf8_error_details(error: &Utf8Error) -> String {
+    match error.error_len() {
+        Some(len) =>
+            format!("valid up to: {}, invalid bytes: {len}", error.valid_up_to()),
+        None => format!("valid up to: {}, incomplete at end", error.valid_up_to()),
+    }
+}
+
+// Like `format_error_chain`, followed by the backtrace captured by `anyhow` if
+// there is one.
+#[cfg(feature = "anyhow")]
+fn format_anyhow_error(error: &anyhow::Error) -> String {
+    let top: &(dyn Error + 'static) = error.as_ref();
+    let mut message = format_sources(describe_error(top), top.source());
+    let backtrace = error.backtrace();
+
+    if backtrace.status() == std::backtrace::BacktraceStatus::Captured {
+        message.push_str(&format!("\n\nBacktrace:\n{backtrace}"));
+    }
+
+    message
+}
+
+// Converts `path` to the forward slash form that rustc's own paths use, so that
+// Windows paths can be checked the same way as Unix ones. The `\\?\` prefix
+// added by `fs::canonicalize` on Windows is dropped as well.
+fn normalize_path(path: &str) -> String {
+    path.strip_prefix(r"\\?\")
+        .unwrap_or(path)
+        .replace('\\', "/")
+}
+
+// Removes the hash that the legacy mangling scheme appends to symbol names,
+// e.g. `foo::bar::h0123456789abcdef`. Names without one, such as those
+// demangled with the v0 scheme, are left as is. The hash is always 16 hex
+// digits, which keeps functions that happen to be called e.g. `hdead` intact.
+fn strip_symbol_hash(name: &str) -> &str {
+    match name.rsplit_once("::") {
+        Some((path, hash))
+            if hash.len() == 17
+                && hash.starts_with('h')
+                && hash[1..].bytes().all(|byte| byte.is_ascii_hexdigit()) =>
+            path,
+        _ => name,
+    }
+}
+
+// Whether a stack frame belongs to this crate, the standard library or a
+// dependency, rather than to the code that created the log.
+fn is_internal_frame(name: &str, file_path: &str) -> bool {
+    let file_path = normalize_path(file_path);
+
+    name.contains("Logger::")
+        || name.contains("codectrl::Logger")
+        || name.contains("LogBatch::")
+        || name.contains("codectrl::LogBatch")
+        || name.contains("InstrumentedCall::")
+        || name == "codectrl"
+        || name.ends_with("create_log")
+        || name.contains("codectrl::create_log")
+        || file_path.contains(".cargo")
+        || file_path.starts_with("/rustc/")
+        || file_path.contains("/rustlib/src/rust/")
+        || !file_path.contains(".rs")
+}
+
+// Adds `warning` to `log` unless it's already there, for warnings that can come
+// up once per stack frame.
+fn push_warning(log: &mut Log, warning: Warning) {
+    let warning = warning.to_string();
+
+    if !log.warnings.contains(&warning) {
+        log.warnings.push(warning);
+    }
+}
+
+// Creates a runtime to send `logs` on. If that fails, they're written to
+// `LoggerConfig::runtime_fallback` if set, so that they aren't lost, and the
+// error is returned either way.
+fn new_runtime<'l>(
+    logs: impl IntoIterator<Item = &'l Log>,
+    config: &LoggerConfig,
+) -> LoggerResult<Runtime> {
+    Runtime::new().map_err(|e| {
+        if let Some(stream) = config.runtime_fallback {
+            if let Err(e) = console::write_logs(stream, logs) {
+                eprintln!("Could not write logs to the runtime fallback: {e}");
+            }
+        }
+
+        e.into()
+    })
+}
+
+// Blocks on `future` with `tokio_runtime` if given, or with a runtime created
+// for sending `logs` otherwise, see `new_runtime`. Blocking on a runtime from a
+// thread that's already running one panics, e.g. when called from async code,
+// so the runtime is run on a thread of its own then, which this one waits for.
+fn block_on<'l, T: Send>(
+    future: impl Future<Output = LoggerResult<T>> + Send,
+    tokio_runtime: Option<&Handle>,
+    logs: impl IntoIterator<Item = &'l Log> + Send,
+    config: &LoggerConfig,
+) -> LoggerResult<T> {
+    match tokio_runtime {
+        Some(handle) => handle.block_on(future),
+        None if Handle::try_current().is_ok() => thread::scope(|scope| {
+            scope
+                .spawn(|| new_runtime(logs, config)?.block_on(future))
+                .join()
+                .unwrap_or_else(|e| panic::resume_unwind(e))
+        }),
+        None => new_runtime(logs, config)?.block_on(future),
+    }
+}
+
+// Whether a log with `level` passes both the minimum level of a logger's own
+// `config` and that of the active config, which `set_level` changes for every
+// logger.
+fn level_enabled(level: Level, config: &LoggerConfig) -> bool {
+    level >= config.level && level >= config::min_level()
+}
+
+// Decides whether a log is picked by `LoggerConfig::sample_rate`.
+fn sampled(sample_rate: f64) -> bool {
+    sample_rate >= 1.0 || rand::random::<f64>() < sample_rate
+}
+
+// Drops the code snippet (and the code of each stack frame) from `log` if it
+// doesn't fit into `LoggerConfig::max_payload_size` bytes, as servers will
+// otherwise reject it. Logs above `LoggerConfig::warn_payload_size` only get a
+// warning.
+fn enforce_payload_size(log: &mut Log, config: &LoggerConfig) -> LoggerResult<()> {
+    let max_size = config.max_payload_size;
+    let mut size = payload_size(log, config)?;
+
+    if config.warn_payload_size.is_some_and(|warn_size| size > warn_size) {
+        push_warning(log, Warning::LargePayload(size));
+
+        // The warning adds to the size itself.
+        size = payload_size(log, config)?;
+    }
+
+    if size <= max_size {
+        return Ok(());
+    }
+
+    log.code_snippet.clear();
+
+    for frame in log.stack.iter_mut() {
+        frame.code.clear();
+    }
+
+    // Logs passed to `Logger::send_many` may have been through here before.
+    push_warning(log, Warning::SnippetDropped);
+
+    let size = payload_size(log, config)?;
+
+    if size > max_size {
+        return Err(LoggerError::PayloadTooLarge { size, max_size });
+    }
+
+    Ok(())
+}
+
+// The size in bytes of `log` as serialised for the transport that `config`
+// sends it over, i.e. by `LoggerConfig::serializer` for `http_url` and
+// `websocket_url`, including what encrypting and signing it adds, and as
+// protobuf for the gRPC server.
+fn payload_size(log: &Log, config: &LoggerConfig) -> LoggerResult<usize> {
+    if config.http_url.is_none() && config.websocket_url.is_none() {
+        return Ok(log.encoded_len());
+    }
+
+    let mut bytes = Vec::new();
+    config.serializer.serialize_into(log, &mut bytes)?;
+
+    let mut size = bytes.len();
+
+    if config.encryption_key.is_some() {
+        size += encryption::OVERHEAD;
+    }
+
+    // The signature is sent in a header over HTTP.
+    if config.websocket_url.is_some() && config.hmac_key.is_some() {
+        size += signature::SIGNATURE_SIZE;
+    }
+
+    Ok(size)
+}
+
+// Returns an error if a payload of `size` bytes, as it's about to be sent over
+// HTTP or WebSocket, exceeds `LoggerConfig::max_payload_size`. This can only
+// happen if a `Serializer` gives logs a different size every time.
+#[cfg(any(feature = "http", feature = "websocket"))]
+fn check_payload_size(size: usize, max_size: usize) -> LoggerResult<()> {
+    if size > max_size {
+        return Err(LoggerError::PayloadTooLarge { size, max_size });
+    }
+
+    Ok(())
+}
+
+/// Connects to the gRPC server at `host` and `port`, for tools that want to
+/// manage the lifecycle of their connections themselves. The TLS, DNS and
+/// failover options of the config set by [`init`] apply, as they do when
+/// sending logs through [`Logger`].
+pub async fn connect(host: &str, port: &str) -> LoggerResult<Connection> {
+    connection::connect(host, port, &config::active_config()).await
+}
+
+/// Decodes a log as sent to [`LoggerConfig::http_url`] or
+/// [`LoggerConfig::websocket_url`], for collectors written in Rust. [`Log`]
+/// isn't generic over the type of the logged message, which is sent already
+/// formatted along with its type name in `message_type`, so logs can be
+/// decoded without knowing that type. Signed or encrypted payloads, see
+/// [`LoggerConfig::hmac_key`] and [`LoggerConfig::encryption_key`], have to
+/// be checked and decrypted before being decoded, and logs serialised by a
+/// custom [`Serializer`] can't be decoded by this.
+///
+/// [`Log`]: codectrl_protobuf_bindings::data::Log
+pub fn decode_log(payload: &[u8]) -> LoggerResult<Log> {
+    ciborium::de::from_reader(payload)
+        .map_err(|e| LoggerError::LoggerError(format!("Could not deserialise log: {e}")))
+}
+
+// Serialises `log` into `payload` with `serializer` for the transports that
+// don't speak protobuf, encrypting it if `encryption_key` is set. The contents of
+// `payload` are replaced but its capacity is kept, so that a connection can
+// reuse the same buffer for every log it sends.
+//
+// TODO: Let a single log override the format it's serialised in, e.g. JSON
+// instead of CBOR while debugging. The HTTP and WebSocket transports send every
+// log in the same format, so this is blocked on a header or message prefix
+// that tells collectors which format each log is in.
+#[cfg(any(feature = "http", feature = "websocket"))]
+fn encode_payload(
+    log: &Log,
+    serializer: &SharedSerializer,
+    encryption_key: Option<&str>,
+    payload: &mut Vec<u8>,
+) -> LoggerResult<()> {
+    serializer.serialize_into(log, payload)?;
+
+    if let Some(key) = encryption_key {
+        encryption::encrypt(key, payload)?;
+    }
+
+    Ok(())
+}
+
+// Returns an error if `LoggerConfig::auth_token` would be sent to `host` in
+// cleartext, i.e. over `http://` or `ws://`, unless `host` is a loopback
+// address, which the token doesn't leave the machine through.
+#[cfg(any(feature = "http", feature = "websocket"))]
+fn refuse_cleartext_auth(
+    host: &str,
+    secure: bool,
+    config: &LoggerConfig,
+) -> LoggerResult<()> {
+    let loopback = host == "localhost"
+        || host
+            .parse::<std::net::IpAddr>()
+            .is_ok_and(|address| address.is_loopback());
+
+    if config.auth_token.is_none() || secure || loopback {
+        return Ok(());
+    }
+
+    Err(LoggerError::LoggerError(format!(
+        "Refusing to send auth_token to {host:?} unencrypted, use an https:// or \
+         wss:// URL instead"
+    )))
+}
+
+// The key that identifies `log`, sent along with it so that the server can
+// tell a log that was resent by a retry apart from a new one, see
+// `LoggerConfig::retries`. It's the UUID that the log was given when it was
+// created, so logs with the same content still get different keys.
+fn idempotency_key(log: &Log) -> Option<&str> {
+    (!log.uuid.is_empty()).then_some(log.uuid.as_str())
+}
+
+// Turns an error reported by the server into a `LoggerError`.
+fn check_response(result: RequestResult) -> LoggerResult<()> {
+    match result {
+        RequestResult { status, .. } if status == RequestStatus::Confirmed.into() =>
+            Ok(()),
+        RequestResult { status, .. } if status == RequestStatus::Error.into() =>
+            Err(result.into()),
+        RequestResult { .. } => unreachable!(),
+    }
+}
+
 /// Type used for generating batch logs to be sent by [`Logger`].
 pub struct LogBatch<'a> {
     logger: Logger<'a>,
     log_batch: VecDeque<Log>,
     tokio_runtime: Option<&'a Handle>,
-    host: &'static str,
-    port: &'static str,
-    surround: u32,
     function_name_occurences: HashBag<&'static str>,
+    message_type: Option<String>,
 }
 
 impl<'a> LogBatch<'a> {
@@ -160,22 +850,47 @@ impl<'a> LogBatch<'a> {
             logger,
             log_batch: VecDeque::new(),
             tokio_runtime: None,
-            host: "127.0.0.1",
-            port: "3002",
-            surround: 3,
             function_name_occurences: HashBag::new(),
+            message_type: None,
+        }
+    }
+
+    // Batched logs are added with `Level::Info`, so they're skipped entirely if
+    // that is below the configured minimum level.
+    fn info_enabled(&self) -> bool {
+        level_enabled(Level::Info, &self.logger.config) && !reentrancy::suppressed()
+    }
+
+    fn push_log<T: Debug>(
+        &mut self,
+        message: T,
+        surround: Surround,
+        function_name: &str,
+    ) {
+        let mut log = create_log(
+            message,
+            surround,
+            Some(function_name),
+            Some(&self.function_name_occurences),
+            &self.logger.config,
+        );
+
+        if let Some(message_type) = &self.message_type {
+            log.message_type = message_type.clone();
         }
+
+        self.log_batch.push_back(log);
     }
 
     /// Sets the host IP address of the gRPC server to connect to.
     pub fn host(mut self, host: &'static str) -> Self {
-        self.host = host;
+        Arc::make_mut(&mut self.logger.config).host = host.into();
         self
     }
 
     /// Sets the port of the `host` gRPC server to connect to.
     pub fn port(mut self, port: &'static str) -> Self {
-        self.port = port;
+        Arc::make_mut(&mut self.logger.config).port = port.into();
         self
     }
 
@@ -187,28 +902,136 @@ impl<'a> LogBatch<'a> {
         self
     }
 
-    /// Sets the surround for the generated code snippet. This value will be
+    /// Sets the surround for the generated code snippet, either as a single
+    /// number of lines for both sides or as a [`Surround`]. This value will be
     /// used where a value isn't manually passed into each `add_X` function,
     /// otherwise the value passed into those functions will take
     /// precedence.
-    pub fn surround(mut self, surround: u32) -> Self {
-        self.surround = surround;
+    pub fn surround(mut self, surround: impl Into<Surround>) -> Self {
+        Arc::make_mut(&mut self.logger.config).surround = surround.into();
+        self
+    }
+
+    /// Sets the maximum size in bytes of each serialised log in the batch. Logs
+    /// exceeding it will have their code snippet dropped before being sent.
+    /// Defaults to [`DEFAULT_MAX_PAYLOAD_SIZE`].
+    pub fn max_payload_size(mut self, max_payload_size: usize) -> Self {
+        Arc::make_mut(&mut self.logger.config).max_payload_size = max_payload_size;
+        self
+    }
+
+    /// Sets the `message_type` reported for the logs added after this call,
+    /// e.g. `"user_event"`, instead of the type name of their message.
+    pub fn message_type(mut self, message_type: &str) -> Self {
+        self.message_type = Some(message_type.into());
         self
     }
 
     /// Batch equivelent of [`Logger::log`]. See [`Logger::log`] for relevant
     /// documentation.
     pub fn add_log<T: Debug>(mut self, message: T, surround: Option<u32>) -> Self {
-        let surround = Some(surround.unwrap_or(self.surround));
+        let surround = surround.map_or(self.logger.config.surround, Surround::from);
 
         self.function_name_occurences.insert("add_log");
 
-        self.log_batch.push_back(create_log(
-            message,
-            surround,
-            Some("add_log"),
-            Some(&self.function_name_occurences),
-        ));
+        if self.info_enabled() {
+            self.push_log(message, surround, "add_log");
+        }
+
+        self
+    }
+
+    /// Batch equivelent of [`Logger::log_lazy`]. See [`Logger::log_lazy`] for
+    /// relevant documentation.
+    pub fn add_lazy_log<T: Debug>(
+        mut self,
+        message: impl FnOnce() -> T,
+        surround: Option<u32>,
+    ) -> Self {
+        let surround = surround.map_or(self.logger.config.surround, Surround::from);
+
+        self.function_name_occurences.insert("add_lazy_log");
+
+        if self.info_enabled() {
+            self.push_log(message(), surround, "add_lazy_log");
+        }
+
+        self
+    }
+
+    /// Batch equivelent of [`Logger::log_raw`]. See [`Logger::log_raw`] for
+    /// relevant documentation.
+    pub fn add_raw_log(
+        mut self,
+        message: impl Into<String>,
+        content_type: &str,
+        surround: Option<u32>,
+    ) -> Self {
+        let surround = surround.map_or(self.logger.config.surround, Surround::from);
+
+        self.function_name_occurences.insert("add_raw_log");
+
+        if self.info_enabled() {
+            self.log_batch.push_back(create_log_with_message(
+                message.into(),
+                content_type.into(),
+                surround,
+                Some("add_raw_log"),
+                Some(&self.function_name_occurences),
+                &self.logger.config,
+            ));
+        }
+
+        self
+    }
+
+    /// Batch equivelent of [`Logger::log_error`]. See [`Logger::log_error`]
+    /// for relevant documentation.
+    pub fn add_error_log<E: Error + ?Sized>(
+        mut self,
+        error: &E,
+        surround: Option<u32>,
+    ) -> Self {
+        let surround = surround.map_or(self.logger.config.surround, Surround::from);
+
+        self.function_name_occurences.insert("add_error_log");
+
+        if self.info_enabled() {
+            self.log_batch.push_back(create_log_with_message(
+                format_error_chain(error),
+                std::any::type_name::<E>().to_string(),
+                surround,
+                Some("add_error_log"),
+                Some(&self.function_name_occurences),
+                &self.logger.config,
+            ));
+        }
+
+        self
+    }
+
+    /// Batch equivelent of [`Logger::log_anyhow`]. See [`Logger::log_anyhow`]
+    /// for relevant documentation.
+    #[cfg(feature = "anyhow")]
+    pub fn add_anyhow_log(
+        mut self,
+        error: &anyhow::Error,
+        surround: Option<u32>,
+    ) -> Self {
+        let surround = surround.map_or(self.logger.config.surround, Surround::from);
+
+        self.function_name_occurences.insert("add_anyhow_log");
+
+        if self.info_enabled() {
+            self.log_batch.push_back(create_log_with_message(
+                format_anyhow_error(error),
+                std::any::type_name::<anyhow::Error>().to_string(),
+                surround,
+                Some("add_anyhow_log"),
+                Some(&self.function_name_occurences),
+                &self.logger.config,
+            ));
+        }
 
         self
     }
@@ -221,17 +1044,12 @@ impl<'a> LogBatch<'a> {
         message: T,
         surround: Option<u32>,
     ) -> Self {
-        let surround = Some(surround.unwrap_or(self.surround));
+        let surround = surround.map_or(self.logger.config.surround, Surround::from);
 
         self.function_name_occurences.insert("add_log_if");
 
-        if condition() {
-            self.log_batch.push_back(create_log(
-                message,
-                surround,
-                Some("add_log_if"),
-                Some(&self.function_name_occurences),
-            ));
+        if self.info_enabled() && condition() {
+            self.push_log(message, surround, "add_log_if");
         }
 
         self
@@ -245,17 +1063,12 @@ impl<'a> LogBatch<'a> {
         message: T,
         surround: Option<u32>,
     ) -> Self {
-        let surround = Some(surround.unwrap_or(self.surround));
+        let surround = surround.map_or(self.logger.config.surround, Surround::from);
 
         self.function_name_occurences.insert("add_boxed_log_if");
 
-        if condition() {
-            self.log_batch.push_back(create_log(
-                message,
-                surround,
-                Some("add_boxed_log_if"),
-                Some(&self.function_name_occurences),
-            ));
+        if self.info_enabled() && condition() {
+            self.push_log(message, surround, "add_boxed_log_if");
         }
 
         self
@@ -268,17 +1081,16 @@ impl<'a> LogBatch<'a> {
         message: T,
         surround: Option<u32>,
     ) -> Self {
-        let surround = Some(surround.unwrap_or(self.surround));
+        let surround = surround.map_or(self.logger.config.surround, Surround::from);
 
         self.function_name_occurences.insert("add_log_when_env");
 
+        if !self.info_enabled() {
+            return self;
+        }
+
         if env::var("CODECTRL_DEBUG").ok().is_some() {
-            self.log_batch.push_back(create_log(
-                message,
-                surround,
-                Some("add_log_when_env"),
-                Some(&self.function_name_occurences),
-            ));
+            self.push_log(message, surround, "add_log_when_env");
         } else {
             #[cfg(debug_assertions)]
             println!("add_log_when_env not called: envvar CODECTRL_DEBUG not present");
@@ -292,12 +1104,8 @@ impl<'a> LogBatch<'a> {
     ///
     /// [`Log`]: codectrl_protobuf_bindings::data::Log
     pub fn build(mut self) -> Logger<'a> {
-        self.logger = Logger {
-            log_batch: self.log_batch,
-            batch_host: self.host,
-            batch_port: self.port,
-            batch_tokio_runtime: self.tokio_runtime,
-        };
+        self.logger.log_batch = self.log_batch;
+        self.logger.batch_tokio_runtime = self.tokio_runtime;
 
         self.logger
     }
@@ -306,81 +1114,190 @@ impl<'a> LogBatch<'a> {
 /// The main type to be used to create and send [`Log`]s to a specified gRPC
 /// server. This is the main "entrypoint" for any usage of this crate.
 ///
+/// Cloning a logger is cheap, so a clone can be handed to each task or thread
+/// that logs. Clones share the background thread and its connections, the
+/// [`Stats`], the callbacks and the circuit breaker, while each has a copy of
+/// the batch that was being built, if any.
+///
 /// [`Log`]: codectrl_protobuf_bindings::data::Log
 #[derive(Debug, Clone, Default)]
 pub struct Logger<'a> {
+    // Only copied when a batch changes it, see `LogBatch`.
+    config: Arc<LoggerConfig>,
     log_batch: VecDeque<Log>,
-    batch_host: &'static str,
-    batch_port: &'static str,
     batch_tokio_runtime: Option<&'a Handle>,
+    background: Option<Background>,
+    counters: Arc<Counters>,
+    callbacks: Arc<Callbacks>,
+    breaker: Arc<CircuitBreaker>,
 }
 
 impl<'a> Logger<'a> {
-    /// Returns a [`LogBatch`], which can be used to start the process of
-    /// generating multiple logs to be sent in a single connection. Should
-    /// be preferred over sending one-time [`Log`]s if possile.
+    /// Creates a new [`Logger`] that uses `config` for any batches started
+    /// with [`Self::batch`].
     ///
-    /// [`Log`]: codectrl_protobuf_bindings::data::Log
-    pub fn start_batch() -> LogBatch<'a> { LogBatch::new(Self::default()) }
+    /// If [`LoggerConfig::background`] is set, this also spawns the background
+    /// thread that sends the logs queued with [`Self::queue_log`]. The thread
+    /// keeps running until the last clone of this logger is dropped.
+    pub fn new(config: LoggerConfig) -> LoggerResult<Self> {
+        config.validate()?;
+
+        let counters = Arc::<Counters>::default();
+        let callbacks = Arc::<Callbacks>::default();
+        let breaker = Arc::<CircuitBreaker>::default();
+
+        let background = match &config.background {
+            Some(options) => Some(Background::spawn(
+                &config,
+                options,
+                Arc::clone(&counters),
+                Arc::clone(&callbacks),
+                Arc::clone(&breaker),
+            )?),
+            None => None,
+        };
 
-    /// Sends the configured batch in `log_batch` to the configured `batch_host`
-    /// and `batch_port`. This _should_ be the preferred way of sending
-    /// multiple logs.
+        Ok(Self {
+            config: Arc::new(config),
+            background,
+            counters,
+            callbacks,
+            breaker,
+            ..Default::default()
+        })
+    }
+
+    /// Registers a callback that is called with each log that this logger or
+    /// one of its clones has sent successfully, replacing any previous one.
     ///
-    /// If given a pre-existing tokio runtime, it _will_ block the executor
-    /// while it waits for the log to complete.
-    pub fn send_batch(&mut self) -> LoggerResult<()> {
-        if self.log_batch.is_empty() {
-            return Err(LoggerError::LoggerError(
-                "Can't send batch: Log batch is empty".to_string(),
+    /// A panic inside the callback is caught and ignored.
+    pub fn on_success<F>(&self, callback: F)
+    where
+        F: Fn(&Log) + Send + Sync + 'static,
+    {
+        *self
+            .callbacks
+            .on_success
+            .write()
+            .unwrap_or_else(|poisoned| poisoned.into_inner()) = Some(Box::new(callback));
+    }
+
+    /// Registers a callback that is called with each log that this logger or
+    /// one of its clones failed to send, along with the error from the last
+    /// attempt, replacing any previous one.
+    ///
+    /// A panic inside the callback is caught and ignored.
+    pub fn on_failure<F>(&self, callback: F)
+    where
+        F: Fn(&Log, &LoggerError) + Send + Sync + 'static,
+    {
+        *self
+            .callbacks
+            .on_failure
+            .write()
+            .unwrap_or_else(|poisoned| poisoned.into_inner()) = Some(Box::new(callback));
+    }
+
+    /// Returns how many logs this logger and its clones have sent, failed to
+    /// send, dropped and retried so far, along with the state of the circuit
+    /// breaker and the size of the background queue. Only batches and queued
+    /// logs are counted, as logs sent through the associated functions such as
+    /// [`Self::log`] don't go through any logger instance.
+    pub fn stats(&self) -> Stats {
+        Stats {
+            circuit: self.breaker.state(self.config.circuit_breaker.as_ref()),
+            queued_bytes: self
+                .background
+                .as_ref()
+                .map_or(0, |background| background.pending_bytes() as u64),
+            ..self.counters.snapshot()
+        }
+    }
+
+    /// Returns a [`LogBatch`], which can be used to start the process of
+    /// generating multiple logs to be sent in a single connection. Should
+    /// be preferred over sending one-time [`Log`]s if possile.
+    ///
+    /// The batch starts out with the config set by [`init`], or the default
+    /// [`LoggerConfig`] if it was never called.
+    ///
+    /// [`Log`]: codectrl_protobuf_bindings::data::Log
+    pub fn start_batch() -> LogBatch<'a> {
+        Self {
+            config: config::active_config().as_ref().clone(),
+            ..Default::default()
+        }
+        .batch()
+    }
+
+    /// Consumes `self` and returns a [`LogBatch`] that starts out with this
+    /// logger's config.
+    pub fn batch(self) -> LogBatch<'a> { LogBatch::new(self) }
+
+    /// Sends the configured batch in `log_batch` to the configured host and
+    /// port. This _should_ be the preferred way of sending multiple logs.
+    ///
+    /// If given a pre-existing tokio runtime, it _will_ block the executor
+    /// while it waits for the log to complete.
+    pub fn send_batch(&mut self) -> LoggerResult<()> {
+        if self.log_batch.is_empty() {
+            return Err(LoggerError::LoggerError(
+                "Can't send batch: Log batch is empty".to_string(),
             ));
         }
 
+        for log in self.log_batch.iter_mut() {
+            enforce_payload_size(log, &self.config)?;
+        }
+
+        // Held until the callbacks have run, so that logs created by them are
+        // suppressed.
+        let _inside = reentrancy::enter();
+
         let mut ret = Ok(());
 
-        async fn send_batch(
-            host: &str,
-            port: &str,
-            logs: &mut VecDeque<Log>,
-        ) -> LoggerResult<()> {
-            let mut log_client =
-                LoggerClient::connect(format!("http://{host}:{port}")).await?;
-
-            let request = Request::new(stream::iter(logs.clone()));
-            let response = log_client.send_logs(request).await?;
-
-            match response.into_inner() {
-                RequestResult { status, .. }
-                    if status == RequestStatus::Confirmed.into() =>
-                    Ok(()),
-                RequestResult {
-                    message,
-                    status,
-                    auth_status,
-                } if status == RequestStatus::Error.into() => Err(RequestResult {
-                    message,
-                    status,
-                    auth_status,
-                }
-                .into()),
-                RequestResult { .. } => unreachable!(),
+        let config: &LoggerConfig = &self.config;
+        let (host, port) = config.destination(Level::Info);
+        let logs = &self.log_batch;
+        let counters = Some(self.counters.as_ref());
+
+        if !self.breaker.allow(config.circuit_breaker.as_ref()) {
+            let ret = Err(LoggerError::CircuitOpen);
+
+            Counters::add(&self.counters.short_circuited, logs.len() as u64);
+
+            for log in logs {
+                self.callbacks.fire(log, &ret);
             }
+
+            return ret;
         }
 
         if let Some(handle) = self.batch_tokio_runtime {
             handle.block_on(async {
-                ret = send_batch(self.batch_host, self.batch_port, &mut self.log_batch)
-                    .await;
+                ret = Self::send_with_retries(config, counters, || {
+                    Self::send_logs(logs, host, port, config)
+                })
+                .await;
             });
         } else {
-            let rt = Runtime::new()?;
+            let rt = new_runtime(logs, config)?;
 
             rt.block_on(async {
-                ret = send_batch(self.batch_host, self.batch_port, &mut self.log_batch)
-                    .await;
+                ret = Self::send_with_retries(config, counters, || {
+                    Self::send_logs(logs, host, port, config)
+                })
+                .await;
             })
         }
 
+        self.breaker.record(&ret, config.circuit_breaker.as_ref());
+        self.counters.record(&ret, logs.len() as u64);
+
+        for log in logs {
+            self.callbacks.fire(log, &ret);
+        }
+
         ret
     }
 
@@ -390,6 +1307,18 @@ impl<'a> Logger<'a> {
     /// with debug_assertions disabled as it will produce a much less
     /// informative log for codeCTRL.
     ///
+    /// Any argument that is `None` is taken from the config set by [`init`].
+    ///
+    /// Returns [`LogOutcome::Sent`] once the server confirmed receiving the
+    /// log. Logs are sent with [`Level::Info`], and return
+    /// [`LogOutcome::Filtered`] without being sent if that is below the
+    /// configured minimum level, if the log isn't
+    /// picked by [`LoggerConfig::sample_rate`], or if it's suppressed as a
+    /// repeat by [`LoggerConfig::dedup_window`]. The level and sample rate are
+    /// checked first, so that `message` is only formatted, and the backtrace
+    /// only captured, for logs that get past them. Suppressing repeats needs
+    /// the formatted message, so it's checked last.
+    ///
     /// If given a pre-existing tokio runtime, it _will_ block the executor
     /// while it waits for the log to complete.
     pub fn log<T: Debug>(
@@ -398,33 +1327,579 @@ impl<'a> Logger<'a> {
         host: Option<&str>,
         port: Option<&str>,
         tokio_runtime: Option<&Handle>,
-    ) -> LoggerResult<()> {
-        let host = host.unwrap_or("127.0.0.1");
-        let port = port.unwrap_or("3002");
+    ) -> LoggerResult<LogOutcome> {
+        Self::log_with_level(
+            Level::Info,
+            |surround, config| create_log(message, surround, None, None, config),
+            surround,
+            host,
+            port,
+            tokio_runtime,
+        )
+    }
 
-        let mut log = create_log(message, surround, None, None);
+    /// Like [`Self::log`], but sent with [`Level::Debug`] instead of
+    /// [`Level::Info`].
+    pub fn debug<T: Debug>(
+        message: T,
+        surround: Option<u32>,
+        host: Option<&str>,
+        port: Option<&str>,
+        tokio_runtime: Option<&Handle>,
+    ) -> LoggerResult<LogOutcome> {
+        Self::log_with_level(
+            Level::Debug,
+            |surround, config| create_log(message, surround, None, None, config),
+            surround,
+            host,
+            port,
+            tokio_runtime,
+        )
+    }
 
-        let mut ret = Ok(());
+    /// Like [`Self::log`], but spelled out for symmetry with [`Self::debug`],
+    /// [`Self::warn`] and [`Self::error`], as [`Level::Info`] is what
+    /// [`Self::log`] sends with anyway.
+    pub fn info<T: Debug>(
+        message: T,
+        surround: Option<u32>,
+        host: Option<&str>,
+        port: Option<&str>,
+        tokio_runtime: Option<&Handle>,
+    ) -> LoggerResult<LogOutcome> {
+        Self::log_with_level(
+            Level::Info,
+            |surround, config| create_log(message, surround, None, None, config),
+            surround,
+            host,
+            port,
+            tokio_runtime,
+        )
+    }
 
-        if let Some(handle) = tokio_runtime {
-            handle.block_on(async {
-                ret = Self::_log(&mut log, host, port).await;
-            });
-        } else {
-            let rt = Runtime::new()?;
+    /// Like [`Self::log`], but sent with [`Level::Warn`] instead of
+    /// [`Level::Info`].
+    pub fn warn<T: Debug>(
+        message: T,
+        surround: Option<u32>,
+        host: Option<&str>,
+        port: Option<&str>,
+        tokio_runtime: Option<&Handle>,
+    ) -> LoggerResult<LogOutcome> {
+        Self::log_with_level(
+            Level::Warn,
+            |surround, config| create_log(message, surround, None, None, config),
+            surround,
+            host,
+            port,
+            tokio_runtime,
+        )
+    }
 
-            rt.block_on(async {
-                ret = Self::_log(&mut log, host, port).await;
+    /// Like [`Self::log`], but sent with [`Level::Error`] instead of
+    /// [`Level::Info`]. Unlike [`Self::log_error`], `message` is formatted with
+    /// [`Debug`] like any other message.
+    pub fn error<T: Debug>(
+        message: T,
+        surround: Option<u32>,
+        host: Option<&str>,
+        port: Option<&str>,
+        tokio_runtime: Option<&Handle>,
+    ) -> LoggerResult<LogOutcome> {
+        Self::log_with_level(
+            Level::Error,
+            |surround, config| create_log(message, surround, None, None, config),
+            surround,
+            host,
+            port,
+            tokio_runtime,
+        )
+    }
+
+    /// Like [`Self::log`], but for call sites that only care about whether the
+    /// log made it, instead of doing `let _ = Logger::log(...)`. Returns
+    /// `false` on any error, including a panic while creating or sending the
+    /// log, and `true` otherwise, even if the log was filtered out.
+    ///
+    /// There's no logger instance to report the error to, so use
+    /// [`Self::log`] instead if the reason matters.
+    pub fn try_log<T: Debug>(
+        message: T,
+        surround: Option<u32>,
+        host: Option<&str>,
+        port: Option<&str>,
+        tokio_runtime: Option<&Handle>,
+    ) -> bool {
+        panic::catch_unwind(AssertUnwindSafe(|| {
+            Self::log(message, surround, host, port, tokio_runtime)
+        }))
+        .is_ok_and(|ret| ret.is_ok())
+    }
+
+    /// Like [`Self::log`], but the message is only created, and formatted, once
+    /// the log is known to pass the configured filters. Useful for messages
+    /// that are expensive to build or to format with [`Debug`].
+    pub fn log_lazy<T: Debug>(
+        message: impl FnOnce() -> T,
+        surround: Option<u32>,
+        host: Option<&str>,
+        port: Option<&str>,
+        tokio_runtime: Option<&Handle>,
+    ) -> LoggerResult<LogOutcome> {
+        Self::log_with_level(
+            Level::Info,
+            |surround, config| create_log(message(), surround, None, None, config),
+            surround,
+            host,
+            port,
+            tokio_runtime,
+        )
+    }
+
+    /// Like [`Self::log`], but for plain text. `text` is sent as-is with
+    /// `text/plain` as its `message_type`, instead of being formatted with
+    /// [`Debug`], which would put it in quotes, and the log is sent with the
+    /// given `level`. Logs don't depend on the type of their message once
+    /// created, so text logs can be stored alongside any others.
+    pub fn message(
+        level: Level,
+        text: &str,
+        surround: Option<u32>,
+        host: Option<&str>,
+        port: Option<&str>,
+        tokio_runtime: Option<&Handle>,
+    ) -> LoggerResult<LogOutcome> {
+        Self::log_with_level(
+            level,
+            |surround, config| {
+                create_log_with_message(
+                    text.into(),
+                    "text/plain".into(),
+                    surround,
+                    None,
+                    None,
+                    config,
+                )
+            },
+            surround,
+            host,
+            port,
+            tokio_runtime,
+        )
+    }
+
+    /// Like [`Self::log`], but for messages that are already serialised, e.g.
+    /// to JSON. The message is sent as-is instead of being formatted with
+    /// [`Debug`], and `content_type` (e.g. `"application/json"`) is reported
+    /// as its `message_type`.
+    pub fn log_raw(
+        message: impl Into<String>,
+        content_type: &str,
+        surround: Option<u32>,
+        host: Option<&str>,
+        port: Option<&str>,
+        tokio_runtime: Option<&Handle>,
+    ) -> LoggerResult<LogOutcome> {
+        Self::log_with_level(
+            Level::Info,
+            |surround, config| {
+                create_log_with_message(
+                    message.into(),
+                    content_type.into(),
+                    surround,
+                    None,
+                    None,
+                    config,
+                )
+            },
+            surround,
+            host,
+            port,
+            tokio_runtime,
+        )
+    }
+
+    /// Sends several related messages together, e.g. the values of a few
+    /// variables at one point of the program. The backtrace and code snippet
+    /// are only captured once and shared by every message, which is sent as a
+    /// log of its own, in a single request. The group is filtered as a whole,
+    /// as if it was one log, while [`LoggerConfig::dedup_window`] doesn't
+    /// apply to it. Returns [`LogOutcome::Filtered`] if `messages` is empty.
+    ///
+    /// If given a pre-existing tokio runtime, it _will_ block the executor
+    /// while it waits for the logs to be sent.
+    //
+    // TODO: Tag the logs of a group with a shared ID, so that collectors can
+    // show them together. Blocked on `Log` gaining a `group_id` field in the
+    // protobuf bindings.
+    pub fn log_group<T: Debug>(
+        messages: &[T],
+        surround: Option<u32>,
+        host: Option<&str>,
+        port: Option<&str>,
+        tokio_runtime: Option<&Handle>,
+    ) -> LoggerResult<LogOutcome> {
+        if messages.is_empty() || Level::Info < config::min_level() {
+            return Ok(LogOutcome::Filtered);
+        }
+
+        let Some(_inside) = reentrancy::enter_for_log() else {
+            return Ok(LogOutcome::Filtered);
+        };
+
+        let config = config::active_config();
+
+        if !sampled(config.sample_rate) {
+            return Ok(LogOutcome::Filtered);
+        }
+
+        let surround = surround.map_or(config.surround, Surround::from);
+        let shared = create_log_with_message(
+            String::new(),
+            std::any::type_name::<T>().to_string(),
+            surround,
+            None,
+            None,
+            &config,
+        );
+
+        let mut logs: Vec<_> = messages
+            .iter()
+            .map(|message| Log {
+                message: format!("{message:#?}"),
+                ..shared.clone()
             })
+            .collect();
+
+        for log in logs.iter_mut() {
+            // Logs that weren't made by the logger may not have a UUID to use
+            // as their idempotency key yet.
+            if log.uuid.is_empty() {
+                log.uuid = Uuid::new_v4().to_string();
+            }
+
+            enforce_payload_size(log, &config)?;
         }
 
-        ret
+        let (default_host, default_port) = config.destination(Level::Info);
+        let host = host.unwrap_or(default_host);
+        let port = port.unwrap_or(default_port);
+
+        let send = Self::send_with_retries(&config, None, || {
+            Self::send_logs(&logs, host, port, &config)
+        });
+
+        block_on(send, tokio_runtime, &logs, &config)?;
+
+        Ok(LogOutcome::Sent)
+    }
+
+    // See `create_logs` for when `create_log` is called.
+    fn log_with_level(
+        level: Level,
+        create_log: impl FnOnce(Surround, &LoggerConfig) -> Log,
+        surround: Option<u32>,
+        host: Option<&str>,
+        port: Option<&str>,
+        tokio_runtime: Option<&Handle>,
+    ) -> LoggerResult<LogOutcome> {
+        let Some(_inside) = reentrancy::enter_for_log() else {
+            return Ok(LogOutcome::Filtered);
+        };
+
+        let Some((logs, config)) = Self::create_logs(level, create_log, surround)? else {
+            return Ok(LogOutcome::Filtered);
+        };
+
+        let (default_host, default_port) = config.destination(level);
+        let host = host.unwrap_or(default_host);
+        let port = port.unwrap_or(default_port);
+
+        let send = Self::_log_all(&logs, host, port, &config);
+
+        block_on(send, tokio_runtime, &logs, &config)?;
+
+        Ok(LogOutcome::Sent)
+    }
+
+    /// Like [`Self::log`], but for async code that's already running on a
+    /// tokio runtime, which the log is sent from without blocking it.
+    pub async fn log_async<T: Debug>(
+        message: T,
+        surround: Option<u32>,
+        host: Option<&str>,
+        port: Option<&str>,
+    ) -> LoggerResult<LogOutcome> {
+        // The guard isn't held while sending, as the task may move to another
+        // thread at any `.await`.
+        let created = reentrancy::enter_for_log().map_or(Ok(None), |_inside| {
+            Self::create_logs(
+                Level::Info,
+                |surround, config| create_log(message, surround, None, None, config),
+                surround,
+            )
+        });
+
+        let Some((logs, config)) = created? else {
+            return Ok(LogOutcome::Filtered);
+        };
+
+        let (default_host, default_port) = config.destination(Level::Info);
+        let host = host.unwrap_or(default_host);
+        let port = port.unwrap_or(default_port);
+
+        Self::_log_all(&logs, host, port, &config).await?;
+
+        Ok(LogOutcome::Sent)
+    }
+
+    /// Like [`Self::log`], but only the log is created on the calling thread.
+    /// Sending it is spawned as a task on the current tokio runtime, which can
+    /// be awaited through the returned handle if the outcome matters. The task
+    /// returns `Ok(())` straight away if the log was filtered out.
+    ///
+    /// # Panics
+    ///
+    /// Panics if called outside of a tokio runtime.
+    pub fn spawn_log<T: Debug>(
+        message: T,
+        surround: Option<u32>,
+        host: Option<&str>,
+        port: Option<&str>,
+    ) -> JoinHandle<LoggerResult<()>> {
+        let created = reentrancy::enter_for_log().map_or(Ok(None), |_inside| {
+            Self::create_logs(
+                Level::Info,
+                |surround, config| create_log(message, surround, None, None, config),
+                surround,
+            )
+        });
+        let host = host.map(str::to_string);
+        let port = port.map(str::to_string);
+
+        tokio::spawn(async move {
+            let Some((logs, config)) = created? else {
+                return Ok(());
+            };
+
+            let (default_host, default_port) = config.destination(Level::Info);
+            let host = host.as_deref().unwrap_or(default_host);
+            let port = port.as_deref().unwrap_or(default_port);
+
+            Logger::_log_all(&logs, host, port, &config).await
+        })
+    }
+
+    // Applies the filters of the active config and creates the logs to send, or
+    // returns `None` if the log was filtered out. `create_log` is only called
+    // once the log is known to pass the filters, with the surround to use.
+    fn create_logs(
+        level: Level,
+        create_log: impl FnOnce(Surround, &LoggerConfig) -> Log,
+        surround: Option<u32>,
+    ) -> LoggerResult<Option<(Vec<Log>, Arc<LoggerConfig>)>> {
+        // Checked before anything else so that filtered out logs don't pay for
+        // the backtrace and code snippet.
+        if level < config::min_level() {
+            return Ok(None);
+        }
+
+        let config = config::active_config();
+
+        if !sampled(config.sample_rate) {
+            return Ok(None);
+        }
+
+        let surround = surround.map_or(config.surround, Surround::from);
+
+        let mut log = create_log(surround, &config);
+        enforce_payload_size(&mut log, &config)?;
+
+        let logs = match config.dedup_window {
+            Some(window) => dedup::deduplicate(log, window),
+            None => vec![log],
+        };
+
+        if logs.is_empty() {
+            return Ok(None);
+        }
+
+        Ok(Some((logs, config)))
+    }
+
+    /// Creates a log like [`Self::log`], but instead of sending it, pushes it
+    /// onto the queue of the background thread spawned by [`Self::new`] and
+    /// returns immediately. Errors that happen while sending are printed to
+    /// stderr by the background thread.
+    ///
+    /// Returns [`LogOutcome::Buffered`] once the log is queued, as whether it
+    /// reaches the server is only known later, see [`Self::on_success`] and
+    /// [`Self::on_failure`]. Returns [`LogOutcome::Filtered`] if the log is
+    /// below the configured minimum level or isn't picked by
+    /// [`LoggerConfig::sample_rate`], if it's suppressed as a repeat by
+    /// [`LoggerConfig::dedup_window`], or if it's created from within the
+    /// logger itself, see [`Stats::reentrant`], and [`LogOutcome::Dropped`] if it was
+    /// dropped because the queue is full, including by
+    /// [`BackgroundConfig::max_bytes`], and [`BackgroundConfig::drop_on_full`]
+    /// is set.
+    pub fn queue_log<T: Debug>(
+        &self,
+        message: T,
+        surround: Option<u32>,
+    ) -> LoggerResult<LogOutcome> {
+        let Some(background) = &self.background else {
+            return Err(LoggerError::LoggerError(
+                "Can't queue log: Background sending isn't enabled in the config"
+                    .to_string(),
+            ));
+        };
+
+        if !level_enabled(Level::Info, &self.config) || !sampled(self.config.sample_rate)
+        {
+            return Ok(LogOutcome::Filtered);
+        }
+
+        let Some(_inside) = reentrancy::enter_for_log() else {
+            return Ok(LogOutcome::Filtered);
+        };
+
+        let surround = surround.map_or(self.config.surround, Surround::from);
+
+        let mut log = create_log(message, surround, None, None, &self.config);
+        enforce_payload_size(&mut log, &self.config)?;
+
+        let logs = match self.config.dedup_window {
+            Some(window) => dedup::deduplicate(log, window),
+            None => vec![log],
+        };
+
+        let mut outcome = LogOutcome::Filtered;
+
+        for log in logs {
+            outcome = if background.queue(log)? {
+                LogOutcome::Buffered
+            } else {
+                Counters::add(&self.counters.dropped, 1);
+                LogOutcome::Dropped
+            };
+        }
+
+        Ok(outcome)
+    }
+
+    /// Blocks until the background thread has finished with every log queued
+    /// so far, or until `timeout` elapses, e.g. to make sure that logs aren't
+    /// lost when the program exits. Returns immediately if background sending
+    /// isn't enabled.
+    pub fn flush(&self, timeout: Duration) -> Flushed {
+        let Some(background) = &self.background else {
+            return Flushed::default();
+        };
+
+        let pending = background.pending();
+        let remaining = background.wait(timeout);
+
+        Flushed {
+            flushed: pending.saturating_sub(remaining),
+            remaining,
+        }
+    }
+
+    /// Like [`Self::flush`], but waits without blocking the async runtime
+    /// it's called from.
+    pub async fn flush_async(&self, timeout: Duration) -> Flushed {
+        let Some(background) = &self.background else {
+            return Flushed::default();
+        };
+
+        let pending = background.pending();
+        let remaining = background.wait_async(timeout).await;
+
+        Flushed {
+            flushed: pending.saturating_sub(remaining),
+            remaining,
+        }
+    }
+
+    /// Like [`Self::queue_log`], but for call sites that don't care whether
+    /// the log makes it. Any error is printed to stderr instead of being
+    /// returned, the same way the background thread reports failed sends.
+    pub fn log_detached<T: Debug>(&self, message: T, surround: Option<u32>) {
+        if let Err(e) = self.queue_log(message, surround) {
+            eprintln!("Failed to queue log: {e}");
+        }
+    }
+
+    /// Like [`Self::log`], but for errors. Instead of `error`'s [`Debug`]
+    /// output, the message contains its [`Display`](fmt::Display) output
+    /// followed by that of each error in its [`Error::source`] chain, one per
+    /// line, so that the root cause is visible. Errors in the chain of commonly
+    /// logged standard library types, such as [`io::Error`] and
+    /// [`ParseIntError`], also get the details that their `Display` output
+    /// leaves out, e.g. their error kind.
+    pub fn log_error<E: Error + ?Sized>(
+        error: &E,
+        surround: Option<u32>,
+        host: Option<&str>,
+        port: Option<&str>,
+        tokio_runtime: Option<&Handle>,
+    ) -> LoggerResult<LogOutcome> {
+        Self::log_with_level(
+            Level::Info,
+            |surround, config| {
+                create_log_with_message(
+                    format_error_chain(error),
+                    std::any::type_name::<E>().to_string(),
+                    surround,
+                    None,
+                    None,
+                    config,
+                )
+            },
+            surround,
+            host,
+            port,
+            tokio_runtime,
+        )
+    }
+
+    /// Like [`Self::log_error`], but for [`anyhow::Error`]s. The message
+    /// contains the error's context chain, followed by its backtrace if one
+    /// was captured (see the [`anyhow`] docs on when that happens).
+    ///
+    /// Requires the `anyhow` feature.
+    #[cfg(feature = "anyhow")]
+    pub fn log_anyhow(
+        error: &anyhow::Error,
+        surround: Option<u32>,
+        host: Option<&str>,
+        port: Option<&str>,
+        tokio_runtime: Option<&Handle>,
+    ) -> LoggerResult<LogOutcome> {
+        Self::log_with_level(
+            Level::Info,
+            |surround, config| {
+                create_log_with_message(
+                    format_anyhow_error(error),
+                    std::any::type_name::<anyhow::Error>().to_string(),
+                    surround,
+                    None,
+                    None,
+                    config,
+                )
+            },
+            surround,
+            host,
+            port,
+            tokio_runtime,
+        )
     }
 
     /// A log function that takes a closure and only logs out if that function
     /// returns `true`. Essentially a conditional wrapper over
     /// [`Self::log`]. See [`Self::boxed_log_if`] for a variation that
-    /// allows for closures that take can take from values in scope.
+    /// allows for closures that take can take from values in scope, or
+    /// [`log_if!`] for one that takes a plain expression.
     ///
     /// If given a pre-existing tokio runtime, it _will_ block the executor
     /// while it waits for the log to complete.
@@ -435,13 +1910,12 @@ impl<'a> Logger<'a> {
         host: Option<&str>,
         port: Option<&str>,
         tokio_runtime: Option<&Handle>,
-    ) -> LoggerResult<bool> {
+    ) -> LoggerResult<LogOutcome> {
         if condition() {
-            Self::log(message, surround, host, port, tokio_runtime)?;
-            return Ok(true);
+            return Self::log(message, surround, host, port, tokio_runtime);
         }
 
-        Ok(false)
+        Ok(LogOutcome::Filtered)
     }
 
     /// A log function, similar to [`Self::log_if`] that takes a boxed closure
@@ -456,13 +1930,31 @@ impl<'a> Logger<'a> {
         host: Option<&str>,
         port: Option<&str>,
         tokio_runtime: Option<&Handle>,
-    ) -> LoggerResult<bool> {
+    ) -> LoggerResult<LogOutcome> {
         if condition() {
-            Self::log(message, surround, host, port, tokio_runtime)?;
-            return Ok(true);
+            return Self::log(message, surround, host, port, tokio_runtime);
+        }
+
+        Ok(LogOutcome::Filtered)
+    }
+
+    /// Like [`Self::boxed_log_if`], but for async code, where deciding whether
+    /// to log may itself need async work, e.g. a database lookup. `condition`
+    /// is awaited first, and the log is only created and sent through
+    /// [`Self::log_async`] if it resolves to `true`. Returns
+    /// [`LogOutcome::Filtered`] otherwise.
+    pub async fn boxed_log_if_async<T: Debug>(
+        condition: impl Future<Output = bool>,
+        message: T,
+        surround: Option<u32>,
+        host: Option<&str>,
+        port: Option<&str>,
+    ) -> LoggerResult<LogOutcome> {
+        if condition.await {
+            return Self::log_async(message, surround, host, port).await;
         }
 
-        Ok(false)
+        Ok(LogOutcome::Filtered)
     }
 
     /// A log function, similar to [`Self::log_if`] and [`Self::boxed_log_if`],
@@ -477,148 +1969,489 @@ impl<'a> Logger<'a> {
         host: Option<&str>,
         port: Option<&str>,
         tokio_runtime: Option<&Handle>,
-    ) -> LoggerResult<bool> {
+    ) -> LoggerResult<LogOutcome> {
         if env::var("CODECTRL_DEBUG").ok().is_some() {
-            Self::log(message, surround, host, port, tokio_runtime)?;
-            Ok(true)
+            Self::log(message, surround, host, port, tokio_runtime)
         } else {
             #[cfg(debug_assertions)]
             println!("log_when_env not called: envvar CODECTRL_DEBUG not present");
 
-            Ok(false)
+            Ok(LogOutcome::Filtered)
         }
     }
 
-    // We have a non-async wrapper over _log so that we can log from non-async
-    // scopes.
+    // TODO: Support request compression (gzip, zstd). tonic's `compression`
+    // feature can't be enabled until the protobuf bindings are generated with
+    // it, and zstd isn't available in tonic before 0.10.
+    //
+    // TODO: Negotiate compression and protocol version with the server at
+    // connection time. This needs a handshake procedure on the `LogClient`
+    // service first, as `GetServerDetails` is only meant for front-ends.
     //
-    // TODO: Provide a direct wrapper so that async environments do not need to call
-    // a non-async wrapper, just for that to call an async wrapper.
-    async fn _log(log: &mut Log, host: &str, port: &str) -> LoggerResult<()> {
-        let mut log_client =
-            LoggerClient::connect(format!("http://{host}:{port}")).await?;
-
-        let request = Request::new(log.clone());
-        let response = log_client.send_log(request).await?;
-
-        match response.into_inner() {
-            RequestResult { status, .. } if status == RequestStatus::Confirmed.into() =>
-                Ok(()),
-            RequestResult {
-                message,
-                status,
-                auth_status,
-            } if status == RequestStatus::Error.into() => Err(RequestResult {
-                message,
-                status,
-                auth_status,
+    // We have a non-async wrapper over _log so that we can log from non-async
+    // scopes. Async scopes use `log_async` instead.
+    async fn _log(
+        log: &Log,
+        host: &str,
+        port: &str,
+        config: &LoggerConfig,
+    ) -> LoggerResult<()> {
+        Self::send_with_retries(config, None, || Self::send_log(log, host, port, config))
+            .await
+    }
+
+    async fn _log_all(
+        logs: &[Log],
+        host: &str,
+        port: &str,
+        config: &LoggerConfig,
+    ) -> LoggerResult<()> {
+        for log in logs {
+            Self::_log(log, host, port, config).await?;
+        }
+
+        Ok(())
+    }
+
+    // Keeps calling `send` until it succeeds or the configured amount of
+    // retries runs out, giving each attempt up to the configured timeout.
+    // Retries are counted in `counters` if given.
+    async fn send_with_retries<F, Fut>(
+        config: &LoggerConfig,
+        counters: Option<&Counters>,
+        mut send: F,
+    ) -> LoggerResult<()>
+    where
+        F: FnMut() -> Fut,
+        Fut: Future<Output = LoggerResult<()>>,
+    {
+        let mut attempts = 0;
+
+        loop {
+            let ret = match config.timeout {
+                Some(timeout) => tokio::time::timeout(timeout, send())
+                    .await
+                    .unwrap_or_else(|elapsed| Err(elapsed.into())),
+                None => send().await,
+            };
+
+            match ret {
+                Err(_) if attempts < config.retries => {
+                    attempts += 1;
+
+                    if let Some(counters) = counters {
+                        Counters::add(&counters.retried, 1);
+                    }
+                },
+                ret => return ret,
             }
-            .into()),
-            RequestResult { .. } => unreachable!(),
         }
     }
 
-    fn get_stack_trace(log: &mut Log) {
-        let backtrace = Backtrace::new();
+    /// Sends logs that were created beforehand, e.g. drained from a buffer, in
+    /// a single request. Logs that exceed the maximum payload size have their
+    /// code snippet dropped first, like any other log.
+    ///
+    /// Any argument that is `None` is taken from the config set by [`init`],
+    /// as are the retries and timeout. Returns how many logs were sent, which
+    /// is all of them unless an error is returned.
+    ///
+    /// If given a pre-existing tokio runtime, it _will_ block the executor
+    /// while it waits for the logs to be sent.
+    pub fn send_many(
+        logs: &[Log],
+        host: Option<&str>,
+        port: Option<&str>,
+        tokio_runtime: Option<&Handle>,
+    ) -> LoggerResult<usize> {
+        let send = Self::send_many_async(logs, host, port);
 
-        for frame in backtrace.frames() {
-            backtrace::resolve(frame.ip(), |symbol| {
-                let name = if let Some(symbol) = symbol.name() {
-                    let mut symbol = symbol.to_string();
-                    let mut split = symbol.split("::").collect::<Vec<&str>>();
+        block_on(send, tokio_runtime, logs, &config::active_config())
+    }
 
-                    if split.len() > 1 {
-                        split.remove(split.len() - 1);
-                    }
+    /// Like [`Self::send_many`], but for async code that's already running on
+    /// a tokio runtime.
+    pub async fn send_many_async(
+        logs: &[Log],
+        host: Option<&str>,
+        port: Option<&str>,
+    ) -> LoggerResult<usize> {
+        if logs.is_empty() {
+            return Ok(0);
+        }
 
-                    symbol = split.join("::");
+        let config = config::active_config();
 
-                    symbol
-                } else {
-                    "".into()
-                };
+        let mut logs = logs.to_vec();
 
-                if let (Some(file_name), Some(line_number)) =
-                    (symbol.filename(), symbol.lineno())
-                {
-                    let column_number = symbol.colno().unwrap_or_default();
+        for log in logs.iter_mut() {
+            // Logs that weren't made by the logger may not have a UUID to use
+            // as their idempotency key yet.
+            if log.uuid.is_empty() {
+                log.uuid = Uuid::new_v4().to_string();
+            }
 
-                    let mut file_path: String =
-                        if let Ok(path) = fs::canonicalize(file_name) {
-                            path.as_os_str().to_str().unwrap().to_string()
-                        } else {
-                            file_name.as_os_str().to_str().unwrap().to_string()
-                        };
+            enforce_payload_size(log, &config)?;
+        }
 
-                    #[cfg(target_os = "windows")]
-                    {
-                        file_path = file_path.replace("\\\\?\\", "");
-                    }
+        let (default_host, default_port) = config.destination(Level::Info);
+        let host = host.unwrap_or(default_host);
+        let port = port.unwrap_or(default_port);
+
+        Self::send_with_retries(&config, None, || {
+            Self::send_logs(&logs, host, port, &config)
+        })
+        .await?;
+
+        Ok(logs.len())
+    }
+
+    /// Sends the logs in a file of newline-delimited JSON, as written by
+    /// [`LoggerConfig::console`] or [`LoggerConfig::runtime_fallback`] with
+    /// the stream redirected to it, e.g. once the server is reachable again.
+    /// The logs are sent one at a time in the order they're in, each with the
+    /// configured retries, and the ones that were sent are removed from the
+    /// file. If one can't be sent or decoded, the error is returned and the
+    /// file keeps that log and the ones after it; otherwise it's left empty.
+    /// Returns how many logs were sent.
+    ///
+    /// Any argument that is `None` is taken from the config set by [`init`].
+    ///
+    /// Returns an error without sending anything if [`LoggerConfig::console`]
+    /// is set, as the logs would only be written out again.
+    ///
+    /// If given a pre-existing tokio runtime, it _will_ block the executor
+    /// while it waits for the logs to be sent. Requires the `console` feature.
+    #[cfg(feature = "console")]
+    pub fn replay(
+        path: impl AsRef<Path>,
+        host: Option<&str>,
+        port: Option<&str>,
+        tokio_runtime: Option<&Handle>,
+    ) -> LoggerResult<usize> {
+        let config = config::active_config();
+
+        if config.console.is_some() {
+            return Err(LoggerError::LoggerError(
+                "Logs can't be replayed while console output is configured".to_string(),
+            ));
+        }
+
+        let path = path.as_ref();
+        let contents = fs::read_to_string(path)?;
+        let lines: Vec<_> = contents
+            .lines()
+            .filter(|line| !line.trim().is_empty())
+            .collect();
+
+        let (default_host, default_port) = config.destination(Level::Info);
+        let host = host.unwrap_or(default_host);
+        let port = port.unwrap_or(default_port);
+
+        let mut sent = 0;
+        let send = async {
+            for line in &lines {
+                let log = console::decode_line(line)?;
+
+                Self::send_with_retries(&config, None, || {
+                    Self::send_log(&log, host, port, &config)
+                })
+                .await?;
+
+                sent += 1;
+            }
+
+            Ok(())
+        };
+
+        // The logs stay in the file if there's no runtime to send them with, so
+        // there's nothing for the runtime fallback to write.
+        let ret = block_on(send, tokio_runtime, [], &config);
+
+        let remaining: String = lines[sent..]
+            .iter()
+            .map(|line| format!("{line}\n"))
+            .collect();
+        fs::write(path, remaining)?;
+
+        ret.map(|()| sent)
+    }
+
+    async fn send_logs<'l>(
+        logs: impl IntoIterator<Item = &'l Log> + Copy,
+        host: &str,
+        port: &str,
+        config: &LoggerConfig,
+    ) -> LoggerResult<()> {
+        if let Some(stream) = config.console {
+            return console::write_logs(stream, logs);
+        }
+
+        if let Some(url) = &config.http_url {
+            let mut payload = Vec::new();
+
+            for log in logs {
+                http::post_log(url, log, &mut payload, config).await?;
+            }
+
+            return Ok(());
+        }
+
+        if let Some(url) = &config.websocket_url {
+            return websocket::send_logs(url, logs, config).await;
+        }
+
+        let mut connection = connection::connect(host, port, config).await?;
+        let logs: Vec<_> = logs.into_iter().cloned().collect();
+
+        connection.send_logs(logs).await
+    }
+
+    async fn send_log(
+        log: &Log,
+        host: &str,
+        port: &str,
+        config: &LoggerConfig,
+    ) -> LoggerResult<()> {
+        if let Some(stream) = config.console {
+            return console::write_logs(stream, [log]);
+        }
+
+        if let Some(url) = &config.http_url {
+            return http::post_log(url, log, &mut Vec::new(), config).await;
+        }
+
+        if let Some(url) = &config.websocket_url {
+            return websocket::send_logs(url, [log], config).await;
+        }
 
-                    if !(name.contains("Logger::")
-                        || name.contains("codectrl::Logger")
-                        || name.contains("LogBatch::")
-                        || name.contains("codectrl::LogBatch")
-                        || name == "codectrl"
-                        || name.ends_with("create_log")
-                        || name.contains("codectrl::create_log")
-                        || file_path.contains(".cargo")
-                        || file_path.starts_with("/rustc/"))
-                        && file_path.contains(".rs")
-                    {
-                        let code = Self::get_code(&file_path, line_number);
-
-                        log.stack.insert(
-                            0,
-                            BacktraceData {
-                                name,
-                                file_path,
-                                line_number,
-                                column_number,
-                                code,
-                            },
-                        );
+        let mut connection = connection::connect(host, port, config).await?;
+
+        connection.send_log(log).await
+    }
+
+    fn get_stack_trace(log: &mut Log, config: &LoggerConfig) {
+        if let Some(max_frames) = config.max_stack_frames {
+            Self::get_stack_trace_lazily(log, max_frames, config);
+            return;
+        }
+
+        let backtrace = Backtrace::new();
+
+        // `Backtrace::new` has already resolved every frame, so the symbols are
+        // reused rather than resolved again. Frames that functions were inlined
+        // into have one symbol per function, each of which gets its own entry.
+        for frame in backtrace.frames() {
+            for symbol in frame.symbols() {
+                Self::add_frame(
+                    log,
+                    symbol.name().map(|name| name.to_string()),
+                    symbol.filename(),
+                    symbol.lineno(),
+                    symbol.colno(),
+                    config,
+                );
+            }
+        }
+    }
+
+    // Like `get_stack_trace`, but only the addresses of the frames are captured
+    // up front. Their symbols are resolved one frame at a time, starting at the
+    // innermost one, until `max_frames` frames have been kept, so that the rest
+    // are never resolved.
+    fn get_stack_trace_lazily(log: &mut Log, max_frames: usize, config: &LoggerConfig) {
+        let mut frames = Vec::new();
+
+        backtrace::trace(|frame| {
+            frames.push(frame.clone());
+            true
+        });
+
+        let mut kept = 0;
+        let mut elided = 0;
+
+        for frame in &frames {
+            backtrace::resolve_frame(frame, |symbol| {
+                let name = symbol.name().map(|name| name.to_string());
+
+                // Frames past the limit are still resolved to count those that
+                // would have been kept, but their code isn't read.
+                if kept < max_frames {
+                    if Self::add_frame(
+                        log,
+                        name,
+                        symbol.filename(),
+                        symbol.lineno(),
+                        symbol.colno(),
+                        config,
+                    ) {
+                        kept += 1;
                     }
+                } else if Self::get_frame(
+                    name,
+                    symbol.filename(),
+                    symbol.lineno(),
+                    symbol.colno(),
+                    config,
+                )
+                .is_some()
+                {
+                    elided += 1;
                 }
             });
         }
+
+        if elided > 0 {
+            push_warning(log, Warning::StackTruncated(elided));
+        }
     }
 
-    fn get_code(file_path: &str, line_number: u32) -> String {
-        let mut code = String::new();
+    // Adds the frame of a resolved symbol to the top of the stack trace, as
+    // frames are resolved from the innermost one outwards. Returns whether the
+    // frame was kept.
+    fn add_frame(
+        log: &mut Log,
+        name: Option<String>,
+        file_name: Option<&Path>,
+        line_number: Option<u32>,
+        column_number: Option<u32>,
+        config: &LoggerConfig,
+    ) -> bool {
+        // A frame that can't be captured because something unexpected went
+        // wrong shouldn't take the whole log down with it, so it's skipped with
+        // a warning instead.
+        match panic::catch_unwind(AssertUnwindSafe(|| {
+            let frame =
+                Self::get_frame(name, file_name, line_number, column_number, config)?;
+
+            let code = if config.capture_snippet {
+                Self::get_code(&frame.file_path, frame.line_number, config)
+            } else {
+                Ok(String::new())
+            };
+
+            Some((frame, code))
+        })) {
+            Ok(Some((frame, code))) => {
+                // Already covered by `NoColumnNumberWindows` on Windows.
+                if frame.column_number == 0 && !cfg!(target_os = "windows") {
+                    push_warning(log, Warning::NoColumnNumber);
+                }
 
-        let file = File::open(file_path).unwrap_or_else(|_| {
-            panic!("Unexpected error: could not open file: {}", file_path)
-        });
+                // The frame is still worth keeping if its source file can't be
+                // read, e.g. because it was removed after the build.
+                let code = code.unwrap_or_else(|_| {
+                    push_warning(log, Warning::CodeUnreadable);
+                    String::new()
+                });
+
+                log.stack.insert(0, frame.with_code(code));
+                true
+            },
+            Ok(None) => false,
+            Err(_) => {
+                push_warning(log, Warning::FrameSkipped);
+                false
+            },
+        }
+    }
 
-        let reader = BufReader::new(file);
+    // Returns `None` for frames that shouldn't be part of the stack trace. The
+    // code of the frame is left empty.
+    fn get_frame(
+        name: Option<String>,
+        file_name: Option<&Path>,
+        line_number: Option<u32>,
+        column_number: Option<u32>,
+        config: &LoggerConfig,
+    ) -> Option<BacktraceData> {
+        // TODO: Behind a config toggle, also send the symbol name as it was
+        // before the hash was stripped, for matching against debugging tools.
+        // Blocked on `BacktraceData` gaining a `raw_name` field in the protobuf
+        // bindings.
+        let name = name
+            .map(|name| strip_symbol_hash(&name).to_string())
+            .unwrap_or_default();
+
+        let (Some(file_name), Some(line_number)) = (file_name, line_number) else {
+            return None;
+        };
+
+        let column_number = column_number.unwrap_or_default();
+
+        // Falls back to the path as reported if it can't be canonicalised, e.g.
+        // because the file is gone.
+        let canonical_path = config
+            .canonicalize_paths
+            .then(|| fs::canonicalize(file_name).ok())
+            .flatten();
+
+        let file_path = canonical_path
+            .as_deref()
+            .unwrap_or(file_name)
+            .to_string_lossy()
+            .into_owned();
+
+        #[cfg(target_os = "windows")]
+        let file_path = normalize_path(&file_path);
+
+        if is_internal_frame(&name, &file_path) {
+            return None;
+        }
+
+        let frame = BacktraceData::new(name, file_path, line_number, column_number);
+
+        if config.skip_crates.iter().any(|name| name == frame.crate_name()) {
+            return None;
+        }
+
+        Some(frame)
+    }
+
+    fn get_code(
+        file_path: &str,
+        line_number: u32,
+        config: &LoggerConfig,
+    ) -> io::Result<String> {
+        let mut code = String::new();
+
+        let reader = sources::open(file_path, config)?;
 
         if let Some(Ok(line)) = reader.lines().nth(line_number.saturating_sub(1) as usize)
         {
-            code = line.trim().to_string();
+            code = strip_bom(&line).trim().to_string();
         }
 
-        code
+        Ok(code)
     }
 
+    // TODO: Stream the lines of large snippets to the server as they're read,
+    // in length-prefixed chunks, instead of collecting them into the `Log`
+    // before it's serialised, to bound memory use with a large surround. This
+    // needs the same custom codec as serialising a borrowed view of the log
+    // (see `Connection::send_log`), and a chunked framing that collectors
+    // understand for the HTTP and WebSocket transports.
     fn get_code_snippet(
         file_path: &str,
         line_number: &mut u32,
-        surround: u32,
+        surround: Surround,
         function_name: &str,
         function_name_occurences: Option<&HashBag<&'static str>>,
+        config: &LoggerConfig,
     ) -> BTreeMap<u32, String> {
-        let file = File::open(file_path).unwrap_or_else(|_| {
-            panic!("Unexpected error: could not open file: {}", file_path)
-        });
-
-        let reader = BufReader::new(file);
+        // The file may have been removed since it was checked for.
+        let Ok(reader) = sources::open(file_path, config) else {
+            return BTreeMap::new();
+        };
 
-        let lines: BTreeMap<u32, String> = reader
+        let mut lines: BTreeMap<u32, String> = reader
             .lines()
             .enumerate()
             .filter(|(_, line)| line.is_ok())
-            .map(|(n, line)| ((n + 1) as u32, line.unwrap()))
+            .map(|(n, line)| match (n, line.unwrap()) {
+                (0, line) => (1, strip_bom(&line).to_string()),
+                (n, line) => ((n + 1) as u32, line),
+            })
             .collect();
 
         if let Some(function_name_occurences) = function_name_occurences {
@@ -669,12 +2502,17 @@ impl<'a> Logger<'a> {
             }
         }
 
-        let offset = line_number.saturating_sub(surround);
-        let end = line_number.saturating_add(surround);
+        let offset = line_number.saturating_sub(surround.before);
+        let end = line_number.saturating_add(surround.after);
+
+        // Moves the lines out rather than cloning them, splitting the rest of the
+        // file off on both sides instead of going through every line.
+        let mut snippet = lines.split_off(&offset);
+
+        if let Some(after_end) = end.checked_add(1) {
+            let _ = snippet.split_off(&after_end);
+        }
 
-        lines
-            .range(offset..=end)
-            .map(|(key, value)| (*key, value.clone()))
-            .collect()
+        snippet
     }
 }
@@ -0,0 +1,46 @@
+use crate::ProxyConfig;
+use std::{error::Error, io};
+use tokio::net::TcpStream;
+use tokio_socks::tcp::Socks5Stream;
+
+// Opens a connection to `host` through the SOCKS5 proxy. The proxy resolves
+// `host` itself, so it doesn't have to be resolvable from this side. Once the
+// tunnel is established, the stream is no different from a direct connection.
+pub(crate) async fn connect(
+    proxy: &ProxyConfig,
+    host: &str,
+    port: u16,
+) -> io::Result<TcpStream> {
+    let address = proxy.address.as_str();
+
+    let stream = match (&proxy.username, &proxy.password) {
+        (Some(username), Some(password)) =>
+            Socks5Stream::connect_with_password(address, (host, port), username, password)
+                .await,
+        _ => Socks5Stream::connect(address, (host, port)).await,
+    }
+    .map_err(io::Error::other)?;
+
+    Ok(stream.into_inner())
+}
+
+// Looks for a SOCKS error anywhere in the chain of sources of `error`, the
+// same way as `tls::handshake_error`.
+pub(crate) fn proxy_error(error: &(dyn Error + 'static)) -> Option<String> {
+    let mut source = Some(error);
+
+    while let Some(error) = source {
+        let inner = error
+            .downcast_ref::<io::Error>()
+            .and_then(io::Error::get_ref)
+            .and_then(|inner| inner.downcast_ref::<tokio_socks::Error>());
+
+        if let Some(socks_error) = error.downcast_ref::<tokio_socks::Error>().or(inner) {
+            return Some(socks_error.to_string());
+        }
+
+        source = error.source();
+    }
+
+    None
+}
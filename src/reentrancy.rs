@@ -0,0 +1,65 @@
+use std::{
+    cell::Cell,
+    sync::atomic::{AtomicBool, AtomicU64, Ordering},
+};
+
+thread_local! {
+    // Set while the current thread is inside the logger, i.e. creating or
+    // sending a log, or running a callback.
+    static INSIDE: Cell<bool> = const { Cell::new(false) };
+}
+
+// Logs suppressed because they were created from within the logger, across
+// every logger.
+static SUPPRESSED: AtomicU64 = AtomicU64::new(0);
+static WARNED: AtomicBool = AtomicBool::new(false);
+
+// Marks the current thread as being inside the logger until it's dropped.
+pub(crate) struct Guard(());
+
+impl Drop for Guard {
+    fn drop(&mut self) { INSIDE.with(|inside| inside.set(false)); }
+}
+
+// Marks the current thread as being inside the logger, or returns `None` if it
+// already is.
+pub(crate) fn enter() -> Option<Guard> {
+    INSIDE.with(|inside| (!inside.replace(true)).then_some(Guard(())))
+}
+
+// Like `enter`, for creating a log, which is suppressed if the current thread
+// is already inside the logger, as it would otherwise recurse, e.g. through a
+// callback that logs the failure of sending a log, which fails in turn.
+pub(crate) fn enter_for_log() -> Option<Guard> {
+    let guard = enter();
+
+    if guard.is_none() {
+        suppress();
+    }
+
+    guard
+}
+
+// Returns whether a log created now would be suppressed, counting it if so.
+pub(crate) fn suppressed() -> bool {
+    let inside = INSIDE.with(Cell::get);
+
+    if inside {
+        suppress();
+    }
+
+    inside
+}
+
+pub(crate) fn count() -> u64 { SUPPRESSED.load(Ordering::Relaxed) }
+
+fn suppress() {
+    SUPPRESSED.fetch_add(1, Ordering::Relaxed);
+
+    if !WARNED.swap(true, Ordering::Relaxed) {
+        eprintln!(
+            "Suppressed a log that was created from within the logger itself, e.g. by \
+             a callback. Further ones are only counted, see `Stats::reentrant`."
+        );
+    }
+}
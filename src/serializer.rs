@@ -0,0 +1,81 @@
+use crate::{LoggerError, LoggerResult};
+use codectrl_protobuf_bindings::data::Log;
+use std::{fmt, sync::Arc};
+
+/// Serialises logs for collectors with a wire format of their own, instead of
+/// the CBOR that is sent to [`LoggerConfig::http_url`] and
+/// [`LoggerConfig::websocket_url`] by default. Set through
+/// [`LoggerConfig::serializer`]. Logs sent to the gRPC server are always
+/// encoded as protobuf.
+///
+/// [`LoggerConfig::http_url`]: crate::LoggerConfig::http_url
+/// [`LoggerConfig::websocket_url`]: crate::LoggerConfig::websocket_url
+/// [`LoggerConfig::serializer`]: crate::LoggerConfig::serializer
+pub trait Serializer: Send + Sync {
+    /// Serialises `log` into the payload of a single HTTP request or WebSocket
+    /// message. It's encrypted and signed afterwards if the config says so.
+    fn serialize(&self, log: &Log) -> LoggerResult<Vec<u8>>;
+
+    /// The `Content-Type` header that logs are POSTed with over HTTP, unless
+    /// they're encrypted.
+    fn content_type(&self) -> &str { "application/octet-stream" }
+}
+
+/// A [`Serializer`] that can be shared between configs, as held by
+/// [`LoggerConfig::serializer`]. Defaults to CBOR.
+///
+/// [`LoggerConfig::serializer`]: crate::LoggerConfig::serializer
+#[derive(Clone, Default)]
+pub struct SharedSerializer(Option<Arc<dyn Serializer>>);
+
+impl SharedSerializer {
+    /// Wraps `serializer` so that it can be set on a config.
+    pub fn new(serializer: impl Serializer + 'static) -> Self {
+        Self(Some(Arc::new(serializer)))
+    }
+
+    // Replaces the contents of `bytes` with `log`, serialised by the wrapped
+    // serializer, or as CBOR by default.
+    pub(crate) fn serialize_into(
+        &self,
+        log: &Log,
+        bytes: &mut Vec<u8>,
+    ) -> LoggerResult<()> {
+        bytes.clear();
+
+        if let Some(serializer) = &self.0 {
+            bytes.extend_from_slice(&serializer.serialize(log)?);
+
+            return Ok(());
+        }
+
+        ciborium::ser::into_writer(log, bytes).map_err(|e| {
+            LoggerError::LoggerError(format!("Could not serialise log: {e}"))
+        })
+    }
+
+    pub(crate) fn content_type(&self) -> &str {
+        self.0
+            .as_ref()
+            .map_or("application/cbor", |serializer| serializer.content_type())
+    }
+}
+
+impl fmt::Debug for SharedSerializer {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_tuple("SharedSerializer")
+            .field(&self.content_type())
+            .finish()
+    }
+}
+
+// Serializers can't be compared, so configs are only equal if they share the
+// same one.
+impl PartialEq for SharedSerializer {
+    fn eq(&self, other: &Self) -> bool {
+        match (&self.0, &other.0) {
+            (Some(serializer), Some(other)) => Arc::ptr_eq(serializer, other),
+            (serializer, other) => serializer.is_none() && other.is_none(),
+        }
+    }
+}
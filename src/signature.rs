@@ -0,0 +1,28 @@
+use crate::LoggerResult;
+
+#[cfg(feature = "hmac")]
+use hmac::{Hmac, Mac};
+#[cfg(feature = "hmac")]
+use sha2::Sha256;
+
+// The size of a signature, which precedes the payload of each WebSocket message.
+pub(crate) const SIGNATURE_SIZE: usize = 32;
+
+// Computes the HMAC-SHA256 of `payload`, which the collector recomputes with
+// its copy of `key` to check that the payload came from a logger that knows
+// the key and wasn't tampered with on the way.
+#[cfg(feature = "hmac")]
+pub(crate) fn sign(key: &str, payload: &[u8]) -> LoggerResult<[u8; SIGNATURE_SIZE]> {
+    let mut mac = Hmac::<Sha256>::new_from_slice(key.as_bytes())
+        .expect("HMAC accepts keys of any length");
+    mac.update(payload);
+
+    Ok(mac.finalize().into_bytes().into())
+}
+
+#[cfg(not(feature = "hmac"))]
+pub(crate) fn sign(_key: &str, _payload: &[u8]) -> LoggerResult<[u8; SIGNATURE_SIZE]> {
+    Err(crate::LoggerError::LoggerError(
+        "Signing logs requires the `hmac` feature to be enabled".to_string(),
+    ))
+}
@@ -0,0 +1,101 @@
+use crate::LoggerConfig;
+use std::{
+    fs::File,
+    io::{self, BufRead, BufReader},
+    path::Path,
+};
+
+#[cfg(feature = "embedded-sources")]
+use include_dir::Dir;
+#[cfg(feature = "mmap")]
+use memmap2::Mmap;
+#[cfg(feature = "mmap")]
+use std::io::Cursor;
+#[cfg(feature = "embedded-sources")]
+use std::sync::OnceLock;
+
+#[cfg(feature = "embedded-sources")]
+static EMBEDDED_SOURCES: OnceLock<&'static Dir<'static>> = OnceLock::new();
+
+/// Sets the source files to read code snippets from when they can't be found
+/// on the filesystem, e.g. in container images that only hold the binary.
+/// They're usually embedded into the binary with the `include_dir` crate:
+///
+/// ```ignore
+/// static SOURCES: include_dir::Dir = include_dir::include_dir!("$CARGO_MANIFEST_DIR/src");
+///
+/// codectrl::embed_sources(&SOURCES);
+/// ```
+///
+/// Only embed the directories that hold source files, as `include_dir!` embeds
+/// everything below the directory it's given, including e.g. `target`. The
+/// path of each file is looked up relative to [`LoggerConfig::source_root`],
+/// which is the directory that was embedded, as it was on the machine that the
+/// binary was built on. Only the first call has an effect.
+///
+/// Requires the `embedded-sources` feature.
+#[cfg(feature = "embedded-sources")]
+pub fn embed_sources(sources: &'static Dir<'static>) {
+    let _ = EMBEDDED_SOURCES.set(sources);
+}
+
+// Opens the source file at `file_path` for reading the code of a log, falling
+// back to the embedded sources if it doesn't exist on the filesystem. The error
+// of opening the real file is returned if neither has it.
+pub(crate) fn open(
+    file_path: &str,
+    config: &LoggerConfig,
+) -> io::Result<Box<dyn BufRead>> {
+    match File::open(file_path) {
+        Ok(file) => Ok(map(&file, config).unwrap_or_else(|| {
+            Box::new(BufReader::with_capacity(config.read_buffer_size, file))
+        })),
+        Err(e) => match embedded(file_path, config) {
+            Some(contents) => Ok(Box::new(contents.as_bytes())),
+            None => Err(e),
+        },
+    }
+}
+
+// Memory-maps `file` if it doesn't fit into a single read through the buffer,
+// so that its lines are scanned in place rather than copied into the buffer
+// one read at a time. Returns `None` for smaller files, or if mapping fails, in
+// which case the file is read through the buffer after all.
+#[cfg(feature = "mmap")]
+fn map(file: &File, config: &LoggerConfig) -> Option<Box<dyn BufRead>> {
+    let len = file.metadata().ok()?.len();
+
+    if len <= config.read_buffer_size as u64 {
+        return None;
+    }
+
+    // SAFETY: Source files aren't expected to change while the program that
+    // was built from them is running. If one is truncated regardless, reading
+    // the mapping past its new end raises `SIGBUS`, the same as for any other
+    // memory-mapped file.
+    let mmap = unsafe { Mmap::map(file) }.ok()?;
+
+    Some(Box::new(Cursor::new(mmap)))
+}
+
+#[cfg(not(feature = "mmap"))]
+fn map(_file: &File, _config: &LoggerConfig) -> Option<Box<dyn BufRead>> { None }
+
+// Whether `open` would find the source file at `file_path`.
+pub(crate) fn exists(file_path: &str, config: &LoggerConfig) -> bool {
+    Path::new(file_path).is_file() || embedded(file_path, config).is_some()
+}
+
+#[cfg(feature = "embedded-sources")]
+fn embedded(file_path: &str, config: &LoggerConfig) -> Option<&'static str> {
+    let sources = EMBEDDED_SOURCES.get()?;
+    let root = config.source_root.as_deref()?;
+    let relative = file_path
+        .strip_prefix(root)?
+        .trim_start_matches(['/', '\\']);
+
+    sources.get_file(relative)?.contents_utf8()
+}
+
+#[cfg(not(feature = "embedded-sources"))]
+fn embedded(_file_path: &str, _config: &LoggerConfig) -> Option<&'static str> { None }
@@ -0,0 +1,223 @@
+use crate::{reentrancy, LoggerResult};
+use std::sync::atomic::{AtomicU64, Ordering};
+
+/// A snapshot of how many logs a [`Logger`] has handled so far, returned by
+/// [`Logger::stats`]. Only logs sent through a logger instance, i.e. batches
+/// and queued logs, are counted, not those sent through [`Logger::log`] and
+/// the other associated functions.
+///
+/// With the `metrics` feature, the same numbers are reported through the
+/// [`metrics`](https://docs.rs/metrics) facade as they change, summed over
+/// every logger in the process, so that any exporter installed with it, e.g.
+/// for Prometheus, picks them up. The counters are `codectrl_logs_sent`,
+/// `codectrl_logs_failed`, `codectrl_logs_dropped`, `codectrl_retries` and
+/// `codectrl_logs_short_circuited`, and the gauges are `codectrl_connections`,
+/// `codectrl_busy_connections`, `codectrl_queued_logs` and
+/// `codectrl_queued_bytes`.
+///
+/// [`Logger`]: crate::Logger
+/// [`Logger::stats`]: crate::Logger::stats
+/// [`Logger::log`]: crate::Logger::log
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Stats {
+    /// Logs that the server confirmed receiving.
+    pub sent: u64,
+    /// Logs that couldn't be sent, even after retrying.
+    pub failed: u64,
+    /// Logs that were dropped because the background queue was full.
+    pub dropped: u64,
+    /// Attempts at sending that failed and were retried.
+    pub retried: u64,
+    /// Logs that weren't sent because the circuit breaker was open, see
+    /// [`LoggerConfig::circuit_breaker`].
+    ///
+    /// [`LoggerConfig::circuit_breaker`]: crate::LoggerConfig::circuit_breaker
+    pub short_circuited: u64,
+    /// The current state of the circuit breaker.
+    pub circuit: CircuitState,
+    /// How many connections the background thread sends over, see
+    /// [`BackgroundConfig::connections`]. `0` if background sending isn't
+    /// enabled.
+    ///
+    /// [`BackgroundConfig::connections`]: crate::BackgroundConfig::connections
+    pub connections: u64,
+    /// How many of those connections are in the middle of sending a log.
+    pub busy_connections: u64,
+    /// The total serialised size of the logs that are queued or being sent by
+    /// the background thread, see [`BackgroundConfig::max_bytes`].
+    ///
+    /// [`BackgroundConfig::max_bytes`]: crate::BackgroundConfig::max_bytes
+    pub queued_bytes: u64,
+    /// Logs that were suppressed because they were created from within the
+    /// logger itself, e.g. by a callback passed to [`Logger::on_failure`],
+    /// which could otherwise recurse. Unlike the other counters, this counts
+    /// the logs of every logger in the process.
+    ///
+    /// [`Logger::on_failure`]: crate::Logger::on_failure
+    pub reentrant: u64,
+}
+
+/// How far [`Logger::flush`] got before returning.
+///
+/// [`Logger::flush`]: crate::Logger::flush
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Flushed {
+    /// Queued logs that the background thread finished with while waiting,
+    /// whether they were sent successfully or not.
+    pub flushed: usize,
+    /// Queued logs that were still waiting to be sent when the timeout
+    /// elapsed.
+    pub remaining: usize,
+}
+
+/// What happened to a log passed to one of the logging functions of
+/// [`Logger`].
+///
+/// [`Logger`]: crate::Logger
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LogOutcome {
+    /// The log was sent, and the server confirmed receiving it.
+    Sent,
+    /// The log was queued for the background thread to send, see
+    /// [`Logger::queue_log`].
+    ///
+    /// [`Logger::queue_log`]: crate::Logger::queue_log
+    Buffered,
+    /// The log was dropped because the background queue was full.
+    Dropped,
+    /// The log wasn't created, as it didn't pass the configured filters or its
+    /// condition, or was suppressed as a repeat or for being created from
+    /// within the logger itself, see [`Stats::reentrant`].
+    Filtered,
+}
+
+/// The state of the circuit breaker of a [`Logger`], see
+/// [`LoggerConfig::circuit_breaker`].
+///
+/// [`Logger`]: crate::Logger
+/// [`LoggerConfig::circuit_breaker`]: crate::LoggerConfig::circuit_breaker
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum CircuitState {
+    /// Logs are sent as usual. This is always the state if the circuit breaker
+    /// isn't enabled.
+    #[default]
+    Closed,
+    /// Sending failed too many times in a row, so logs aren't sent until the
+    /// cooldown has passed.
+    Open,
+    /// The cooldown has passed, so the next log is sent to check whether the
+    /// server is back. The circuit is closed again if it is, and opened again
+    /// otherwise.
+    HalfOpen,
+}
+
+// The live counters behind `Stats`, shared between the clones of a `Logger`
+// and its background thread.
+#[derive(Debug)]
+pub(crate) struct Counters {
+    pub(crate) sent: Counter,
+    pub(crate) failed: Counter,
+    pub(crate) dropped: Counter,
+    pub(crate) retried: Counter,
+    pub(crate) short_circuited: Counter,
+    pub(crate) connections: Counter,
+    pub(crate) busy_connections: Counter,
+}
+
+impl Default for Counters {
+    fn default() -> Self {
+        Self {
+            sent: Counter::new("codectrl_logs_sent", false),
+            failed: Counter::new("codectrl_logs_failed", false),
+            dropped: Counter::new("codectrl_logs_dropped", false),
+            retried: Counter::new("codectrl_retries", false),
+            short_circuited: Counter::new("codectrl_logs_short_circuited", false),
+            connections: Counter::new("codectrl_connections", true),
+            busy_connections: Counter::new("codectrl_busy_connections", true),
+        }
+    }
+}
+
+// One of the numbers in `Counters`, along with the name that it's reported
+// under with the `metrics` feature.
+#[derive(Debug)]
+pub(crate) struct Counter {
+    value: AtomicU64,
+    #[cfg_attr(not(feature = "metrics"), allow(dead_code))]
+    metric: &'static str,
+    // Gauges can go down again, so they're reported as such rather than as
+    // counters.
+    #[cfg_attr(not(feature = "metrics"), allow(dead_code))]
+    gauge: bool,
+}
+
+impl Counter {
+    fn new(metric: &'static str, gauge: bool) -> Self {
+        Self {
+            value: AtomicU64::new(0),
+            metric,
+            gauge,
+        }
+    }
+
+    fn get(&self) -> u64 { self.value.load(Ordering::Relaxed) }
+}
+
+impl Counters {
+    pub(crate) fn add(counter: &Counter, amount: u64) {
+        counter.value.fetch_add(amount, Ordering::Relaxed);
+
+        if counter.gauge {
+            adjust_gauge(counter.metric, amount as f64);
+        } else {
+            increment_counter(counter.metric, amount);
+        }
+    }
+
+    // Only meant for gauges, as counters can't go down.
+    pub(crate) fn sub(counter: &Counter, amount: u64) {
+        counter.value.fetch_sub(amount, Ordering::Relaxed);
+        adjust_gauge(counter.metric, -(amount as f64));
+    }
+
+    // Counts `logs` as sent or failed, depending on the outcome of sending
+    // them.
+    pub(crate) fn record(&self, ret: &LoggerResult<()>, logs: u64) {
+        match ret {
+            Ok(()) => Self::add(&self.sent, logs),
+            Err(_) => Self::add(&self.failed, logs),
+        }
+    }
+
+    pub(crate) fn snapshot(&self) -> Stats {
+        Stats {
+            sent: self.sent.get(),
+            failed: self.failed.get(),
+            dropped: self.dropped.get(),
+            retried: self.retried.get(),
+            short_circuited: self.short_circuited.get(),
+            circuit: CircuitState::Closed,
+            connections: self.connections.get(),
+            busy_connections: self.busy_connections.get(),
+            queued_bytes: 0,
+            reentrant: reentrancy::count(),
+        }
+    }
+}
+
+// Reports a change of the gauge `metric` through the `metrics` facade.
+#[cfg(feature = "metrics")]
+pub(crate) fn adjust_gauge(metric: &'static str, delta: f64) {
+    metrics::increment_gauge!(metric, delta);
+}
+
+#[cfg(not(feature = "metrics"))]
+pub(crate) fn adjust_gauge(_metric: &'static str, _delta: f64) {}
+
+#[cfg(feature = "metrics")]
+fn increment_counter(metric: &'static str, amount: u64) {
+    metrics::counter!(metric, amount);
+}
+
+#[cfg(not(feature = "metrics"))]
+fn increment_counter(_metric: &'static str, _amount: u64) {}
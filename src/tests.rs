@@ -1,7 +1,8 @@
 #![allow(dead_code)]
 #![cfg(test)]
 
-use crate::Logger;
+use crate::{BackgroundConfig, Level, LogOutcome, Logger, LoggerConfig, Surround};
+use codectrl_protobuf_bindings::data::Log;
 use std::{thread::sleep, time::Duration};
 
 #[test]
@@ -97,3 +98,1410 @@ fn log_batch_layer_3() {
         panic!("{e}");
     }
 }
+
+#[test]
+fn queue_log() {
+    let logger = Logger::new(LoggerConfig {
+        background: Some(BackgroundConfig {
+            connections: 2,
+            ..Default::default()
+        }),
+        ..Default::default()
+    })
+    .unwrap();
+
+    assert_eq!(logger.stats().connections, 2);
+
+    assert!(matches!(
+        logger.queue_log("Hello, queued", Some(2)),
+        Ok(LogOutcome::Buffered)
+    ));
+    assert!(Logger::default().queue_log("Not queued", None).is_err());
+
+    let flushed = logger.flush(Duration::from_secs(5));
+    assert_eq!(flushed.remaining, 0);
+    assert_eq!(logger.flush(Duration::ZERO), Default::default());
+
+    let unsampled = Logger::new(LoggerConfig {
+        background: Some(BackgroundConfig::default()),
+        sample_rate: 0.0,
+        ..Default::default()
+    })
+    .unwrap();
+
+    assert!(matches!(
+        unsampled.queue_log("Not sampled", None),
+        Ok(LogOutcome::Filtered)
+    ));
+}
+
+#[test]
+fn queue_max_bytes() {
+    // Never answers, so that queued logs stay pending.
+    let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+
+    let logger = Logger::new(LoggerConfig {
+        port: listener.local_addr().unwrap().port().to_string(),
+        background: Some(BackgroundConfig {
+            max_bytes: Some(1),
+            ..Default::default()
+        }),
+        ..Default::default()
+    })
+    .unwrap();
+
+    // The first log is queued despite its size, as nothing else is pending.
+    assert!(matches!(
+        logger.queue_log("Hello, first", None),
+        Ok(LogOutcome::Buffered)
+    ));
+    assert!(matches!(
+        logger.queue_log("Hello, second", None),
+        Ok(LogOutcome::Dropped)
+    ));
+
+    let stats = logger.stats();
+    assert_eq!(stats.dropped, 1);
+    assert!(stats.queued_bytes > 1);
+}
+
+#[test]
+fn message_type() {
+    let logger = Logger::default()
+        .batch()
+        .add_log(1, None)
+        .message_type("user_event")
+        .add_log(2, None)
+        .build();
+
+    let message_types: Vec<_> = logger
+        .log_batch
+        .iter()
+        .map(|log| log.message_type.as_str())
+        .collect();
+
+    assert_eq!(message_types, ["i32", "user_event"]);
+}
+
+#[test]
+fn add_lazy_log() {
+    let logger = Logger::new(LoggerConfig {
+        level: Level::Error,
+        ..Default::default()
+    })
+    .unwrap()
+    .batch()
+    .add_lazy_log(
+        || -> i32 { panic!("Filtered out logs shouldn't be created") },
+        None,
+    )
+    .build();
+
+    assert!(logger.log_batch.is_empty());
+}
+
+#[test]
+fn add_raw_log() {
+    let logger = Logger::default()
+        .batch()
+        .add_raw_log(r#"{"event":"signup"}"#, "application/json", None)
+        .build();
+
+    let log = &logger.log_batch[0];
+
+    assert_eq!(log.message, r#"{"event":"signup"}"#);
+    assert_eq!(log.message_type, "application/json");
+    assert!(log
+        .stack
+        .iter()
+        .all(|frame| !frame.name.contains("create_log")));
+}
+
+#[test]
+fn log_if_macro() {
+    let mut evaluated = false;
+    let sent = crate::log_if!(false, Level::Info, {
+        evaluated = true;
+        1
+    });
+
+    assert_eq!(sent.unwrap(), LogOutcome::Filtered);
+    assert!(!evaluated);
+}
+
+#[test]
+fn log_macro_non_string_literal() {
+    let sent = crate::log_if!(false, Level::Info, 42);
+    assert_eq!(sent.unwrap(), LogOutcome::Filtered);
+
+    assert_eq!(
+        crate::call_site::literal_message(42),
+        ("42".to_string(), "i32".to_string())
+    );
+    assert_eq!(
+        crate::call_site::literal_message('c'),
+        ("'c'".to_string(), "char".to_string())
+    );
+    assert_eq!(
+        crate::call_site::literal_message("Listening"),
+        ("Listening".to_string(), "text/plain".to_string())
+    );
+}
+
+#[test]
+fn log_macro_in_runtime() {
+    // Fails without a server to send to, but mustn't panic.
+    let _ = tokio::runtime::Runtime::new()
+        .unwrap()
+        .block_on(async { crate::log!(Level::Info, "Hello, {}", "runtime") });
+}
+
+#[test]
+fn boxed_log_if_async() {
+    let sent = tokio::runtime::Runtime::new().unwrap().block_on(async {
+        Logger::boxed_log_if_async(async { false }, 1, None, None, None).await
+    });
+
+    assert_eq!(sent.unwrap(), LogOutcome::Filtered);
+}
+
+#[test]
+fn log_group_empty() {
+    let sent = Logger::log_group::<u32>(&[], None, None, None, None);
+
+    assert_eq!(sent.unwrap(), LogOutcome::Filtered);
+}
+
+#[test]
+fn log_group_in_runtime() {
+    // Fails without a server to send to, but mustn't panic.
+    let _ = tokio::runtime::Runtime::new()
+        .unwrap()
+        .block_on(async { Logger::log_group(&[1, 2], None, None, None, None) });
+}
+
+#[test]
+fn send_many() {
+    let (port, received) = capture_logs();
+    let logs = Logger::default()
+        .batch()
+        .add_log("First", None)
+        .add_log("Second", None)
+        .build()
+        .log_batch;
+    let logs: Vec<_> = logs.into_iter().collect();
+
+    assert_eq!(
+        Logger::send_many(&logs, Some("127.0.0.1"), Some(&port), None).unwrap(),
+        2
+    );
+    assert_eq!(Logger::send_many(&[], None, Some("1"), None).unwrap(), 0);
+    assert_eq!(received.try_iter().count(), 2);
+}
+
+#[test]
+fn send_many_in_runtime() {
+    let logs = [Log::default()];
+
+    // Fails without a server to send to, but mustn't panic.
+    let _ = tokio::runtime::Runtime::new()
+        .unwrap()
+        .block_on(async { Logger::send_many(&logs, None, None, None) });
+}
+
+#[test]
+fn logger_clone() {
+    use std::sync::Arc;
+
+    let logger = Logger::new(LoggerConfig::default()).unwrap();
+    let clone = logger.clone();
+
+    assert!(Arc::ptr_eq(&logger.config, &clone.config));
+    assert!(Arc::ptr_eq(&logger.counters, &clone.counters));
+
+    let batch = clone.batch().port("3003").build();
+
+    assert_eq!(batch.config.port, "3003");
+    assert_eq!(logger.config.port, LoggerConfig::default().port);
+}
+
+#[test]
+fn reentrant_log() {
+    // Formatting this logs, as e.g. a `Debug` impl that traces could.
+    struct Reentrant;
+
+    impl std::fmt::Debug for Reentrant {
+        fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+            let outcome = Logger::log(1, None, None, None, None);
+
+            assert_eq!(outcome.unwrap(), LogOutcome::Filtered);
+
+            f.write_str("Reentrant")
+        }
+    }
+
+    let suppressed = crate::reentrancy::count();
+    let _ = Logger::log(Reentrant, None, None, None, None);
+
+    assert!(crate::reentrancy::count() > suppressed);
+}
+
+#[test]
+fn deduplicate() {
+    use crate::dedup::deduplicate;
+
+    let log = |message: &str| Log {
+        message: message.into(),
+        file_name: "dedup.rs".into(),
+        line_number: 7,
+        ..Default::default()
+    };
+    let window = Duration::from_secs(60);
+
+    assert_eq!(deduplicate(log("retrying"), window).len(), 1);
+    assert!(deduplicate(log("retrying"), window).is_empty());
+    assert!(deduplicate(log("retrying"), window).is_empty());
+
+    let logs = deduplicate(log("gave up"), window);
+    let messages: Vec<_> = logs.iter().map(|log| log.message.as_str()).collect();
+
+    assert_eq!(messages, ["retrying (repeated 2 times)", "gave up"]);
+}
+
+#[test]
+fn circuit_breaker() {
+    use crate::{CircuitBreakerConfig, CircuitState, LoggerError};
+
+    // Nothing listens on port 1, so every send fails.
+    let logger = Logger::new(LoggerConfig {
+        port: "1".into(),
+        circuit_breaker: Some(CircuitBreakerConfig {
+            failures: 2,
+            cooldown: Duration::from_secs(60),
+        }),
+        ..Default::default()
+    })
+    .unwrap();
+
+    let send = || {
+        logger
+            .clone()
+            .batch()
+            .add_log("Hello", None)
+            .build()
+            .send_batch()
+    };
+
+    assert!(send().is_err());
+    assert_eq!(logger.stats().circuit, CircuitState::Closed);
+    assert!(send().is_err());
+    assert_eq!(logger.stats().circuit, CircuitState::Open);
+    assert!(matches!(send(), Err(LoggerError::CircuitOpen)));
+    assert_eq!(logger.stats().short_circuited, 1);
+}
+
+#[test]
+fn instrument() {
+    let (port, logs) = capture_logs();
+
+    let add = |a: i32, b: i32| {
+        crate::instrument!(Some("127.0.0.1"), Some(port.as_str()); add(a, b) {
+            a + b
+        })
+    };
+
+    assert_eq!(add(1, 2), 3);
+
+    let entry = logs.recv_timeout(Duration::from_secs(5)).unwrap();
+    let exit = logs.recv_timeout(Duration::from_secs(5)).unwrap();
+
+    // `[call 0123abcd] `
+    let (id, entry) = entry.message.split_at(16);
+    assert_eq!(entry, "-> add(a = 1, b = 2)");
+    assert!(exit.message.starts_with(id));
+    assert!(exit.message[16..].starts_with("<- add returned 3 after "));
+    assert_eq!(exit.file_name, "src/tests.rs");
+}
+
+#[test]
+fn instrument_async() {
+    let (port, logs) = capture_logs();
+
+    let doubled = tokio::runtime::Runtime::new().unwrap().block_on(async {
+        let a = 2;
+
+        crate::instrument!(Some("127.0.0.1"), Some(port.as_str()); double(a) async {
+            tokio::task::yield_now().await;
+            a * 2
+        })
+        .await
+    });
+
+    assert_eq!(doubled, 4);
+
+    let entry = logs.recv_timeout(Duration::from_secs(5)).unwrap();
+    let exit = logs.recv_timeout(Duration::from_secs(5)).unwrap();
+
+    assert!(entry.message.ends_with("-> double(a = 2)"));
+    assert!(exit.message[16..].starts_with("<- double returned 4 after "));
+}
+
+#[test]
+fn create_log_at_call_site() {
+    use crate::CallSite;
+
+    let call_site = CallSite {
+        file: file!(),
+        line: line!(),
+        column: column!(),
+        module_path: module_path!(),
+        manifest_dir: env!("CARGO_MANIFEST_DIR"),
+    };
+    let log = crate::create_log_at(
+        String::new(),
+        String::new(),
+        1.into(),
+        None,
+        None,
+        Some(&call_site),
+        &LoggerConfig {
+            capture_stack: false,
+            ..Default::default()
+        },
+    );
+
+    assert_eq!(log.line_number, call_site.line);
+    assert!(log.file_name.ends_with("tests.rs"));
+    assert!(log.code_snippet[&call_site.line].contains("line!()"));
+    assert_eq!(log.stack.len(), 1);
+    assert_eq!(log.stack[0].name, module_path!());
+}
+
+#[test]
+fn capture_flags() {
+    let build = |config: LoggerConfig| {
+        Logger::new(config)
+            .unwrap()
+            .batch()
+            .add_log("Hello, cheap", None)
+            .build()
+            .log_batch[0]
+            .clone()
+    };
+
+    let log = build(LoggerConfig {
+        capture_snippet: false,
+        ..Default::default()
+    });
+    assert!(!log.stack.is_empty());
+    assert!(log.code_snippet.is_empty());
+    assert!(log.stack.iter().all(|frame| frame.code.is_empty()));
+
+    let log = build(LoggerConfig {
+        capture_stack: false,
+        ..Default::default()
+    });
+    assert!(log.stack.is_empty());
+    assert!(log.code_snippet.is_empty());
+    assert_eq!(log.message, "\"Hello, cheap\"");
+
+    let full = build(LoggerConfig::default());
+    let log = build(LoggerConfig {
+        max_stack_frames: Some(2),
+        ..Default::default()
+    });
+    assert_eq!(log.stack.len(), 2);
+    assert_eq!(log.stack[1].file_path, full.stack.last().unwrap().file_path);
+    assert_eq!(log.stack[1].name, full.stack.last().unwrap().name);
+    assert!(log.warnings.iter().any(|warning| warning.contains(&format!(
+        "leaving out {} outer frames",
+        full.stack.len() - 2
+    ))));
+
+    let log = build(LoggerConfig {
+        max_stack_frames: Some(full.stack.len()),
+        ..Default::default()
+    });
+    assert!(!log
+        .warnings
+        .iter()
+        .any(|warning| warning.contains("max_stack_frames")));
+}
+
+#[test]
+fn trim_indentation() {
+    use codectrl_protobuf_bindings::data::{BacktraceData, Log};
+
+    let mut log = Log {
+        code_snippet: [
+            (1, "        if ready {".to_string()),
+            (2, "            send();".to_string()),
+            (3, "   ".to_string()),
+            (4, "        }".to_string()),
+        ]
+        .into(),
+        stack: vec![BacktraceData {
+            column_number: 13,
+            ..Default::default()
+        }],
+        ..Default::default()
+    };
+
+    crate::trim_indentation(&mut log);
+
+    let lines: Vec<_> = log.code_snippet.values().map(String::as_str).collect();
+    assert_eq!(lines, ["if ready {", "    send();", "", "}"]);
+    assert_eq!(log.stack[0].column_number, 5);
+}
+
+#[test]
+fn remap_path() {
+    let remap = [
+        ("/home/alice".to_string(), "~".to_string()),
+        ("/home/alice/projects/app".to_string(), "<app>".to_string()),
+    ]
+    .into();
+
+    assert_eq!(
+        crate::remap_path("/home/alice/projects/app/src/main.rs", &remap),
+        "<app>/src/main.rs"
+    );
+    assert_eq!(
+        crate::remap_path("/home/alice/notes.rs", &remap),
+        "~/notes.rs"
+    );
+    assert_eq!(
+        crate::remap_path("/srv/app/main.rs", &remap),
+        "/srv/app/main.rs"
+    );
+}
+
+#[test]
+fn strip_base_dir() {
+    use crate::strip_base_dir;
+
+    let base_dir = "/home/alice/projects/app";
+
+    assert_eq!(
+        strip_base_dir("/home/alice/projects/app/src/main.rs", base_dir),
+        "src/main.rs"
+    );
+    assert_eq!(
+        strip_base_dir("/home/alice/projects/application/src/main.rs", base_dir),
+        "/home/alice/projects/application/src/main.rs"
+    );
+    assert_eq!(
+        strip_base_dir("/usr/src/lib.rs", base_dir),
+        "/usr/src/lib.rs"
+    );
+}
+
+#[test]
+fn surround_from_toml() {
+    let surround = |toml: &str| toml::from_str::<LoggerConfig>(toml).unwrap().surround;
+
+    assert_eq!(surround("surround = 2"), Surround::from(2));
+    assert_eq!(
+        surround("surround = { before = 5, after = 1 }"),
+        Surround {
+            before: 5,
+            after: 1,
+        }
+    );
+}
+
+#[test]
+fn snippet_without_trailing_newline() {
+    let path = concat!(
+        env!("CARGO_MANIFEST_DIR"),
+        "/tests/fixtures/no_trailing_newline.txt"
+    );
+    let config = LoggerConfig::default();
+    let snippet = |mut line_number: u32, surround: Surround| {
+        Logger::get_code_snippet(path, &mut line_number, surround, "", None, &config)
+    };
+
+    let lines = snippet(5, 2.into());
+    assert_eq!(lines.keys().copied().collect::<Vec<_>>(), [3, 4, 5]);
+    assert_eq!(lines[&5], "}");
+
+    let lines = snippet(4, 1.into());
+    assert_eq!(lines.keys().copied().collect::<Vec<_>>(), [3, 4, 5]);
+
+    let lines = snippet(
+        4,
+        Surround {
+            before: 2,
+            after: 0,
+        },
+    );
+    assert_eq!(lines.keys().copied().collect::<Vec<_>>(), [2, 3, 4]);
+}
+
+#[test]
+fn snippet_with_bom() {
+    let path = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/fixtures/bom.txt");
+    let config = LoggerConfig::default();
+
+    let mut line_number = 1;
+    let snippet =
+        Logger::get_code_snippet(path, &mut line_number, 1.into(), "", None, &config);
+    assert_eq!(snippet[&1], "fn main() {");
+    assert_eq!(Logger::get_code(path, 1, &config).unwrap(), "fn main() {");
+}
+
+#[test]
+fn snippet_missing_file() {
+    let path = "/missing/src/main.rs";
+    let config = LoggerConfig::default();
+
+    let mut line_number = 1;
+    let snippet =
+        Logger::get_code_snippet(path, &mut line_number, 1.into(), "", None, &config);
+    assert!(snippet.is_empty());
+    assert!(Logger::get_code(path, 1, &config).is_err());
+}
+
+#[cfg(feature = "embedded-sources")]
+#[test]
+fn embedded_sources() {
+    use std::io::BufRead;
+
+    static SOURCES: include_dir::Dir =
+        include_dir::include_dir!("$CARGO_MANIFEST_DIR/src");
+
+    crate::embed_sources(&SOURCES);
+
+    let config = LoggerConfig {
+        source_root: Some("/missing/app/src".into()),
+        ..Default::default()
+    };
+    let first_line = crate::sources::open("/missing/app/src/tests.rs", &config)
+        .unwrap()
+        .lines()
+        .next()
+        .unwrap()
+        .unwrap();
+
+    assert_eq!(first_line, include_str!("tests.rs").lines().next().unwrap());
+    assert!(crate::sources::open("/missing/other/tests.rs", &config).is_err());
+}
+
+#[cfg(feature = "mmap")]
+#[test]
+fn mmap_sources() {
+    use std::io::BufRead;
+
+    // Larger than the buffer, so that it's mapped.
+    let config = LoggerConfig {
+        read_buffer_size: 64,
+        ..Default::default()
+    };
+    let lines: Vec<_> = crate::sources::open(file!(), &config)
+        .unwrap()
+        .lines()
+        .map(Result::unwrap)
+        .collect();
+
+    assert_eq!(lines, include_str!("tests.rs").lines().collect::<Vec<_>>());
+}
+
+#[test]
+fn skip_crates() {
+    use std::path::Path;
+
+    let config = LoggerConfig {
+        skip_crates: vec!["tokio".into()],
+        capture_snippet: false,
+        ..Default::default()
+    };
+    let frame = |name: &str| {
+        Logger::get_frame(
+            Some(name.into()),
+            Some(Path::new(file!())),
+            Some(1),
+            Some(1),
+            &config,
+        )
+    };
+
+    assert!(frame("tokio::runtime::park::Inner::park").is_none());
+    assert!(frame("<tokio::runtime::Runtime as core::ops::Drop>::drop").is_none());
+    assert!(frame("tokio_util::codec::Framed::poll").is_some());
+    assert!(frame("app::main").is_some());
+}
+
+#[test]
+fn unreadable_frame_code() {
+    use std::path::Path;
+
+    let mut log = Log::default();
+    let kept = Logger::add_frame(
+        &mut log,
+        Some("app::main".into()),
+        Some(Path::new("/nonexistent/src/main.rs")),
+        Some(1),
+        Some(1),
+        &LoggerConfig::default(),
+    );
+
+    assert!(kept);
+    assert_eq!(log.stack.len(), 1);
+    assert!(log.stack[0].code.is_empty());
+    assert!(log
+        .warnings
+        .iter()
+        .any(|warning| warning.contains("couldn't be read")));
+}
+
+#[test]
+fn is_internal_frame_windows() {
+    use crate::is_internal_frame;
+
+    assert!(is_internal_frame(
+        "std::rt::lang_start",
+        r"\rustc\90b35a6239c3d8bdabc530a6a0816f7ff89a0aaf\library\std\src\rt.rs"
+    ));
+    assert!(is_internal_frame(
+        "tokio::runtime::park",
+        r"\\?\C:\Users\alice\.cargo\registry\src\tokio-1.18.0\src\park.rs"
+    ));
+    assert!(is_internal_frame(
+        "core::ops::function::FnOnce::call_once",
+        r"C:\Users\alice\.rustup\toolchains\stable\lib\rustlib\src\rust\library\core\src\ops\function.rs"
+    ));
+    assert!(!is_internal_frame(
+        "app::main",
+        r"\\?\C:\Users\alice\projects\app\src\main.rs"
+    ));
+}
+
+#[test]
+fn strip_symbol_hash() {
+    use crate::strip_symbol_hash;
+
+    assert_eq!(
+        strip_symbol_hash("app::server::handle::h5d3f0b0a9c8e7d61"),
+        "app::server::handle"
+    );
+    assert_eq!(
+        strip_symbol_hash("app::server::handle"),
+        "app::server::handle"
+    );
+    assert_eq!(strip_symbol_hash("app::cache::hdead"), "app::cache::hdead");
+    assert_eq!(strip_symbol_hash("main"), "main");
+}
+
+#[test]
+fn module_path() {
+    use crate::BacktraceDataExt;
+    use codectrl_protobuf_bindings::data::BacktraceData;
+
+    let frame = |name: &str| BacktraceData::new(name, "src/main.rs", 1, 1);
+    let module_path = |name: &str| frame(name).module_path().to_string();
+
+    assert_eq!(module_path("app::server::handle"), "app::server");
+    assert_eq!(module_path("app::main::{{closure}}"), "app");
+    assert_eq!(module_path("app::main::{closure#0}::{closure#0}"), "app");
+    assert_eq!(
+        module_path("<app::config::Config as core::fmt::Debug>::fmt"),
+        "app::config"
+    );
+    assert_eq!(
+        module_path("<app::Wrapper<alloc::string::String>>::new"),
+        "app"
+    );
+    assert_eq!(module_path("app::config::Config::new"), "app::config");
+    assert_eq!(module_path("app::Config<T>::new"), "app");
+    assert_eq!(
+        module_path("app::Config<alloc::vec::Vec<u8>>::new::{{closure}}"),
+        "app"
+    );
+    assert_eq!(module_path("app::Handler<fn() -> u8>::call"), "app");
+    assert_eq!(module_path("main"), "");
+    assert_eq!(frame("app::server::handle").crate_name(), "app");
+    assert_eq!(frame("app::main").crate_name(), "app");
+    assert_eq!(frame("tokio::sync::Mutex<T>::lock").crate_name(), "tokio");
+}
+
+#[test]
+fn log_origin() {
+    use crate::{BacktraceDataExt, LogExt};
+    use codectrl_protobuf_bindings::data::BacktraceData;
+
+    let mut log = Log::default();
+
+    assert_eq!(log.origin(), None);
+
+    log.stack = vec![
+        BacktraceData::new("app::main", "src/main.rs", 10, 5),
+        BacktraceData::new("app::server::handle", "src/server.rs", 42, 9),
+    ];
+
+    assert_eq!(log.origin(), Some(("src/server.rs", 42, 9)));
+}
+
+#[test]
+fn warn_payload_size() {
+    let config = LoggerConfig {
+        warn_payload_size: Some(64),
+        ..Default::default()
+    };
+    let mut log = Log {
+        message: "x".repeat(128),
+        ..Default::default()
+    };
+
+    crate::enforce_payload_size(&mut log, &config).unwrap();
+
+    assert_eq!(log.message.len(), 128);
+    assert!(log
+        .warnings
+        .iter()
+        .any(|warning| warning.contains("warn_payload_size")));
+}
+
+#[test]
+fn snippet_dropped_once() {
+    let config = LoggerConfig {
+        max_payload_size: 256,
+        ..Default::default()
+    };
+    let mut log = Log {
+        code_snippet: [(1, "x".repeat(512))].into(),
+        ..Default::default()
+    };
+
+    crate::enforce_payload_size(&mut log, &config).unwrap();
+    log.code_snippet.insert(1, "x".repeat(512));
+    crate::enforce_payload_size(&mut log, &config).unwrap();
+
+    assert!(log.code_snippet.is_empty());
+    assert_eq!(log.warnings.len(), 1);
+}
+
+#[test]
+fn payload_size() {
+    use crate::payload_size;
+
+    let log = Log {
+        message: "x".repeat(128),
+        ..Default::default()
+    };
+    let http = LoggerConfig {
+        http_url: Some("http://127.0.0.1:3003/logs".into()),
+        ..Default::default()
+    };
+    let websocket = LoggerConfig {
+        websocket_url: Some("ws://127.0.0.1:3003/logs".into()),
+        encryption_key: Some("00".repeat(32)),
+        hmac_key: Some("key".into()),
+        ..Default::default()
+    };
+    let size = payload_size(&log, &http).unwrap();
+
+    assert_ne!(size, payload_size(&log, &LoggerConfig::default()).unwrap());
+    assert_eq!(payload_size(&log, &websocket).unwrap(), size + 28 + 32);
+}
+
+#[test]
+fn custom_serializer() {
+    use crate::{LoggerResult, Serializer, SharedSerializer};
+
+    struct Fixed;
+
+    impl Serializer for Fixed {
+        fn serialize(&self, _: &Log) -> LoggerResult<Vec<u8>> { Ok(vec![0; 10]) }
+    }
+
+    let cbor = LoggerConfig {
+        http_url: Some("http://127.0.0.1:3003/logs".into()),
+        ..Default::default()
+    };
+    let fixed = LoggerConfig {
+        serializer: SharedSerializer::new(Fixed),
+        ..cbor.clone()
+    };
+
+    assert_eq!(crate::payload_size(&Log::default(), &fixed).unwrap(), 10);
+    assert_eq!(fixed.serializer.content_type(), "application/octet-stream");
+    assert_eq!(cbor.serializer.content_type(), "application/cbor");
+    assert_eq!(fixed.clone(), fixed);
+    assert_ne!(fixed, cbor);
+}
+
+#[test]
+fn idempotency_key() {
+    let config = LoggerConfig::default();
+    let log = || crate::create_log("a", Surround::from(0), None, None, &config);
+    let (first, second) = (log(), log());
+    let key = crate::idempotency_key(&first).unwrap();
+
+    assert_eq!(key.len(), 36);
+    assert_eq!(Some(key), crate::idempotency_key(&first.clone()));
+    assert_ne!(Some(key), crate::idempotency_key(&second));
+    assert_eq!(crate::idempotency_key(&Default::default()), None);
+}
+
+#[test]
+fn decode_log() {
+    let log = Log {
+        message: "\"Hello, collector\"".into(),
+        message_type: "&str".into(),
+        line_number: 3,
+        ..Default::default()
+    };
+    let mut payload = Vec::new();
+    ciborium::ser::into_writer(&log, &mut payload).unwrap();
+
+    assert_eq!(crate::decode_log(&payload).unwrap(), log);
+    assert!(crate::decode_log(&payload[..payload.len() / 2]).is_err());
+}
+
+#[test]
+fn validate_config() {
+    use crate::{ConfigError, Destination, ProxyConfig};
+
+    let invalid_field = |config: LoggerConfig| match config.validate() {
+        Err(ConfigError::InvalidValue { field, .. }) => field,
+        ret => panic!("expected an invalid value, got {ret:?}"),
+    };
+
+    assert!(LoggerConfig::default().validate().is_ok());
+    assert_eq!(
+        invalid_field(LoggerConfig {
+            surround: 5_000_000.into(),
+            ..Default::default()
+        }),
+        "surround"
+    );
+    assert_eq!(
+        invalid_field(LoggerConfig {
+            timeout: Some(Duration::ZERO),
+            ..Default::default()
+        }),
+        "timeout"
+    );
+    assert_eq!(
+        invalid_field(LoggerConfig {
+            failover: vec![Destination {
+                host: "".into(),
+                port: "3002".into(),
+            }],
+            ..Default::default()
+        }),
+        "failover[0].host"
+    );
+    assert_eq!(
+        invalid_field(LoggerConfig {
+            proxy: Some(ProxyConfig {
+                address: "127.0.0.1:1080".into(),
+                username: Some("alice".into()),
+                password: None,
+            }),
+            ..Default::default()
+        }),
+        "proxy"
+    );
+    assert_eq!(
+        invalid_field(LoggerConfig {
+            hmac_key: Some("secret".into()),
+            ..Default::default()
+        }),
+        "hmac_key"
+    );
+    assert_eq!(
+        invalid_field(LoggerConfig {
+            source_root: Some(String::new()),
+            ..Default::default()
+        }),
+        "source_root"
+    );
+    assert_eq!(
+        invalid_field(LoggerConfig {
+            read_buffer_size: 0,
+            ..Default::default()
+        }),
+        "read_buffer_size"
+    );
+    assert_eq!(
+        invalid_field(LoggerConfig {
+            max_stack_frames: Some(0),
+            ..Default::default()
+        }),
+        "max_stack_frames"
+    );
+    assert_eq!(
+        invalid_field(LoggerConfig {
+            http_url: Some("http://127.0.0.1:3003/logs".into()),
+            encryption_key: Some("not hex".into()),
+            ..Default::default()
+        }),
+        "encryption_key"
+    );
+    assert_eq!(
+        invalid_field(LoggerConfig {
+            http_url: Some("http://127.0.0.1:3003/logs".into()),
+            console: Some(crate::Console::Stdout),
+            ..Default::default()
+        }),
+        "console"
+    );
+    assert_eq!(
+        invalid_field(LoggerConfig {
+            auth_token: Some("not a token".into()),
+            ..Default::default()
+        }),
+        "auth_token"
+    );
+    assert!(Logger::new(LoggerConfig {
+        port: "not a port".into(),
+        ..Default::default()
+    })
+    .is_err());
+}
+
+#[test]
+fn format_error_chain() {
+    let error = anyhow::anyhow!("connection refused")
+        .context("could not reach database")
+        .context("failed to load user");
+
+    assert_eq!(
+        crate::format_error_chain(AsRef::<dyn std::error::Error>::as_ref(&error)),
+        "failed to load user\n\nCaused by:\n    0: could not reach database\n    1: \
+         connection refused"
+    );
+}
+
+#[test]
+fn describe_error() {
+    use std::io;
+
+    let error =
+        anyhow::Error::new(io::Error::new(io::ErrorKind::NotFound, "config.toml"))
+            .context("failed to load config");
+    let parse_error = "80a".parse::<u16>().unwrap_err();
+
+    assert_eq!(
+        crate::format_error_chain(AsRef::<dyn std::error::Error>::as_ref(&error)),
+        "failed to load config\n\nCaused by:\n    0: config.toml (kind: NotFound)"
+    );
+    assert_eq!(
+        crate::describe_error(&parse_error),
+        "invalid digit found in string (kind: InvalidDigit)"
+    );
+    assert_eq!(
+        crate::describe_error(&String::from_utf8(vec![b'a', 0xff]).unwrap_err()),
+        "invalid utf-8 sequence of 1 bytes from index 1 (valid up to: 1, invalid \
+         bytes: 1)"
+    );
+}
+
+#[test]
+fn connect() {
+    let (port, logs) = capture_logs();
+    let log = Logger::default()
+        .batch()
+        .add_log("Hello, connection", None)
+        .build()
+        .log_batch[0]
+        .clone();
+
+    tokio::runtime::Runtime::new()
+        .unwrap()
+        .block_on(async {
+            let mut connection = crate::connect("127.0.0.1", &port).await?;
+
+            connection.send_log(&log).await?;
+            connection.send_logs(vec![log.clone(), log]).await
+        })
+        .unwrap();
+
+    assert_eq!(logs.try_iter().count(), 3);
+}
+
+// Starts a gRPC server on an ephemeral port that captures every log it
+// receives, so that tests can assert on what actually went over the wire.
+// Returns the port along with the receiving end of the captured logs.
+fn capture_logs() -> (String, std::sync::mpsc::Receiver<Log>) {
+    use codectrl_protobuf_bindings::logs_service::{
+        LogClientService, LogClientTrait, RequestResult, RequestStatus,
+    };
+    use futures_util::{stream, StreamExt};
+    use std::sync::{mpsc, Mutex};
+    use tonic::{transport::Server, Request, Response, Status, Streaming};
+
+    struct Capture(Mutex<mpsc::Sender<Log>>);
+
+    impl Capture {
+        fn capture(&self, log: Log) -> Result<Response<RequestResult>, Status> {
+            let _ = self.0.lock().unwrap().send(log);
+
+            Ok(Response::new(RequestResult {
+                message: String::new(),
+                status: RequestStatus::Confirmed.into(),
+                auth_status: None,
+            }))
+        }
+    }
+
+    #[tonic::async_trait]
+    impl LogClientTrait for Capture {
+        async fn send_log(
+            &self,
+            request: Request<Log>,
+        ) -> Result<Response<RequestResult>, Status> {
+            self.capture(request.into_inner())
+        }
+
+        async fn send_logs(
+            &self,
+            request: Request<Streaming<Log>>,
+        ) -> Result<Response<RequestResult>, Status> {
+            let logs: Vec<_> = request.into_inner().collect().await;
+            let mut ret = Err(Status::invalid_argument("No logs were sent"));
+
+            for log in logs {
+                ret = self.capture(log?);
+            }
+
+            ret
+        }
+    }
+
+    let (sender, receiver) = mpsc::channel();
+    let (port_sender, port_receiver) = mpsc::channel();
+
+    std::thread::spawn(move || {
+        let rt = tokio::runtime::Runtime::new().unwrap();
+
+        rt.block_on(async {
+            let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+            port_sender
+                .send(listener.local_addr().unwrap().port().to_string())
+                .unwrap();
+
+            let incoming = stream::unfold(listener, |listener| async {
+                let stream = listener.accept().await.map(|(stream, _)| stream);
+                Some((stream, listener))
+            });
+
+            Server::builder()
+                .add_service(LogClientService::new(Capture(Mutex::new(sender))))
+                .serve_with_incoming(incoming)
+                .await
+                .unwrap();
+        });
+    });
+
+    (port_receiver.recv().unwrap(), receiver)
+}
+
+#[test]
+fn log_over_the_wire() {
+    let (port, logs) = capture_logs();
+
+    assert_eq!(
+        Logger::log("Hello, wire", Some(1), Some("127.0.0.1"), Some(&port), None)
+            .unwrap(),
+        LogOutcome::Sent
+    );
+
+    let log = logs.recv_timeout(Duration::from_secs(5)).unwrap();
+
+    assert_eq!(log.message, "\"Hello, wire\"");
+    assert_eq!(log.message_type, "&str");
+    assert_eq!(log.file_name, "src/tests.rs");
+    assert_eq!(log.code_snippet.len(), 3);
+    assert!(log.code_snippet[&log.line_number].contains("Hello, wire"));
+
+    assert!(Logger::try_log(
+        "Hello",
+        None,
+        Some("127.0.0.1"),
+        Some(&port),
+        None
+    ));
+    assert!(!Logger::try_log(
+        "Hello",
+        None,
+        Some("127.0.0.1"),
+        Some("1"),
+        None
+    ));
+    logs.recv_timeout(Duration::from_secs(5)).unwrap();
+
+    let rt = tokio::runtime::Runtime::new().unwrap();
+    let task = {
+        let _guard = rt.enter();
+        Logger::spawn_log("Spawned", None, Some("127.0.0.1"), Some(&port))
+    };
+    rt.block_on(task).unwrap().unwrap();
+
+    let log = logs.recv_timeout(Duration::from_secs(5)).unwrap();
+    assert_eq!(log.message, "\"Spawned\"");
+}
+
+#[cfg(feature = "console")]
+#[test]
+fn replay_invalid_line() {
+    let path = std::env::temp_dir()
+        .join(format!("codectrl-replay-{}", std::process::id()));
+    std::fs::write(&path, "\nnot json\n{}\n").unwrap();
+
+    assert!(Logger::replay(&path, None, None, None).is_err());
+    assert_eq!(std::fs::read_to_string(&path).unwrap(), "not json\n{}\n");
+
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[cfg(feature = "tls")]
+#[test]
+fn parse_fingerprint() {
+    use crate::tls::parse_fingerprint;
+
+    let hex = "9f86d081884c7d659a2feaa0c55ad015a3bf4f1b2b0b822cd15d6c15b0f00a08";
+    let with_colons = hex
+        .as_bytes()
+        .chunks(2)
+        .map(|pair| std::str::from_utf8(pair).unwrap())
+        .collect::<Vec<_>>()
+        .join(":");
+
+    let fingerprint = parse_fingerprint(hex).unwrap();
+    assert_eq!(fingerprint[0], 0x9f);
+    assert_eq!(fingerprint[31], 0x08);
+    assert_eq!(parse_fingerprint(&with_colons).unwrap(), fingerprint);
+    assert!(parse_fingerprint(&hex[2..]).is_err());
+    assert!(parse_fingerprint(&hex.replace('9', "g")).is_err());
+}
+
+#[cfg(feature = "socks")]
+#[test]
+fn connect_through_proxy() {
+    use crate::{LoggerError, ProxyConfig};
+    use std::{
+        io::{Read, Write},
+        net::TcpListener,
+        thread,
+    };
+
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let address = listener.local_addr().unwrap();
+
+    // Accepts the greeting without authentication, then refuses to connect to
+    // whichever host it's asked to, see RFC 1928.
+    let proxy = thread::spawn(move || {
+        let (mut stream, _) = listener.accept().unwrap();
+
+        let mut greeting = [0; 2];
+        stream.read_exact(&mut greeting).unwrap();
+        let mut methods = vec![0; greeting[1] as usize];
+        stream.read_exact(&mut methods).unwrap();
+        stream.write_all(&[5, 0]).unwrap();
+
+        let mut request = [0; 5];
+        stream.read_exact(&mut request).unwrap();
+        let mut host = vec![0; request[4] as usize];
+        let mut port = [0; 2];
+        stream.read_exact(&mut host).unwrap();
+        stream.read_exact(&mut port).unwrap();
+        stream.write_all(&[5, 5, 0, 1, 0, 0, 0, 0, 0, 0]).unwrap();
+
+        (
+            request[3],
+            String::from_utf8(host).unwrap(),
+            u16::from_be_bytes(port),
+        )
+    });
+
+    let ret = Logger::new(LoggerConfig {
+        host: "collector.internal".into(),
+        proxy: Some(ProxyConfig {
+            address: address.to_string(),
+            ..Default::default()
+        }),
+        ..Default::default()
+    })
+    .unwrap()
+    .batch()
+    .add_log("Hello, proxy", None)
+    .build()
+    .send_batch();
+
+    // The host name is left to the proxy to resolve.
+    assert_eq!(
+        proxy.join().unwrap(),
+        (3, "collector.internal".to_string(), 3002)
+    );
+    assert!(matches!(ret, Err(LoggerError::ProxyError(_))));
+}
+
+#[cfg(all(feature = "hmac", any(feature = "http", feature = "websocket")))]
+#[test]
+fn sign() {
+    // Test case 2 of RFC 4231.
+    let signature =
+        crate::signature::sign("Jefe", b"what do ya want for nothing?").unwrap();
+    let hex: String = signature.iter().map(|byte| format!("{byte:02x}")).collect();
+
+    assert_eq!(
+        hex,
+        "5bdcc146bf60754e6a042426089575c75a003f089d2739839dec58b964ec3843"
+    );
+}
+
+#[cfg(all(feature = "encryption", any(feature = "http", feature = "websocket")))]
+#[test]
+fn encrypt() {
+    use crate::encryption::NONCE_SIZE;
+    use chacha20poly1305::{
+        aead::{Aead, KeyInit},
+        ChaCha20Poly1305, Key, Nonce,
+    };
+
+    let key = "00112233445566778899aabbccddeeff00112233445566778899aabbccddeeff";
+    let mut payload = b"log".to_vec();
+    crate::encryption::encrypt(key, &mut payload).unwrap();
+
+    // Nonce, then the ciphertext followed by its 16-byte tag.
+    assert_eq!(payload.len(), NONCE_SIZE + 3 + 16);
+
+    let key: Vec<u8> = (0..32)
+        .map(|i| u8::from_str_radix(&key[i * 2..i * 2 + 2], 16).unwrap())
+        .collect();
+    let (nonce, ciphertext) = payload.split_at(NONCE_SIZE);
+    let plaintext = ChaCha20Poly1305::new(Key::from_slice(&key))
+        .decrypt(Nonce::from_slice(nonce), ciphertext)
+        .unwrap();
+
+    assert_eq!(plaintext, b"log");
+}
+
+#[cfg(feature = "http")]
+#[test]
+fn post_log_rejected() {
+    use crate::LoggerError;
+    use std::{
+        io::{Read, Write},
+        net::TcpListener,
+        thread,
+    };
+
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let address = listener.local_addr().unwrap();
+
+    let server = thread::spawn(move || {
+        let (mut stream, _) = listener.accept().unwrap();
+        let mut request = [0; 4096];
+        let read = stream.read(&mut request).unwrap();
+
+        stream
+            .write_all(b"HTTP/1.1 403 Forbidden\r\nContent-Length: 6\r\n\r\ndenied")
+            .unwrap();
+
+        String::from_utf8_lossy(&request[..read]).into_owned()
+    });
+
+    let ret = Logger::new(LoggerConfig {
+        http_url: Some(format!("http://{address}/logs")),
+        ..Default::default()
+    })
+    .unwrap()
+    .batch()
+    .add_log("Hello, HTTP", None)
+    .build()
+    .send_batch();
+
+    let request = server.join().unwrap();
+
+    assert!(request.starts_with("POST /logs HTTP/1.1\r\n"));
+    assert!(request
+        .to_lowercase()
+        .contains(&format!("host: {address}\r\n")));
+    assert!(request
+        .to_lowercase()
+        .contains("content-type: application/cbor\r\n"));
+    assert!(matches!(
+        ret,
+        Err(LoggerError::Rejected { status: 403, body }) if body == "denied"
+    ));
+}
+
+#[cfg(any(feature = "http", feature = "websocket"))]
+#[test]
+fn cleartext_auth() {
+    use crate::refuse_cleartext_auth;
+
+    let config = LoggerConfig {
+        auth_token: Some("token".into()),
+        ..Default::default()
+    };
+
+    assert!(refuse_cleartext_auth("example.com", false, &config).is_err());
+    assert!(refuse_cleartext_auth("example.com", true, &config).is_ok());
+    assert!(refuse_cleartext_auth("127.0.0.1", false, &config).is_ok());
+    assert!(refuse_cleartext_auth("::1", false, &config).is_ok());
+    assert!(refuse_cleartext_auth("example.com", false, &Default::default()).is_ok());
+}
+
+#[cfg(any(feature = "http", feature = "websocket"))]
+#[test]
+fn reuse_payload_buffer() {
+    let log = Log {
+        message: "x".repeat(256),
+        ..Default::default()
+    };
+    let serializer = crate::SharedSerializer::default();
+    let mut payload = Vec::new();
+
+    crate::encode_payload(&log, &serializer, None, &mut payload).unwrap();
+    let (len, capacity) = (payload.len(), payload.capacity());
+    crate::encode_payload(&Log::default(), &serializer, None, &mut payload).unwrap();
+
+    assert!(payload.len() < len);
+    assert_eq!(payload.capacity(), capacity);
+}
+
+#[cfg(feature = "websocket")]
+#[test]
+fn websocket_send_batch() {
+    use std::{net::TcpListener, thread};
+    use tokio_tungstenite::tungstenite::{self, Message};
+
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let address = listener.local_addr().unwrap();
+
+    let server = thread::spawn(move || {
+        let (stream, _) = listener.accept().unwrap();
+        let mut websocket = tungstenite::accept(stream).unwrap();
+
+        // Answered by the client while it's connected.
+        websocket.write_message(Message::Ping(b"ping".to_vec())).unwrap();
+
+        loop {
+            match websocket.read_message().unwrap() {
+                Message::Binary(payload) => return payload,
+                Message::Pong(_) => continue,
+                message => panic!("unexpected message {message:?}"),
+            }
+        }
+    });
+
+    Logger::new(LoggerConfig {
+        websocket_url: Some(format!("ws://{address}/logs")),
+        ..Default::default()
+    })
+    .unwrap()
+    .batch()
+    .add_log("Hello, WebSocket", None)
+    .build()
+    .send_batch()
+    .unwrap();
+
+    let payload = server.join().unwrap();
+    let log: Log = ciborium::de::from_reader(payload.as_slice()).unwrap();
+
+    assert_eq!(log.message, "\"Hello, WebSocket\"");
+}
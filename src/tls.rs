@@ -0,0 +1,232 @@
+use crate::{LoggerError, LoggerResult, Pem, TlsConfig};
+use rustls::{
+    client::{ServerCertVerified, ServerCertVerifier, WebPkiVerifier},
+    Certificate, ClientConfig, OwnedTrustAnchor, PrivateKey, RootCertStore, ServerName,
+};
+use rustls_pemfile::Item;
+use sha2::{Digest, Sha256};
+use std::{
+    convert::TryFrom,
+    error::Error,
+    fs::File,
+    io::{self, BufReader},
+    net::SocketAddr,
+    sync::Arc,
+    time::SystemTime,
+};
+use tokio::net::TcpStream;
+use tokio_rustls::{client::TlsStream, TlsConnector};
+
+// Everything needed to open a TLS connection to any of the addresses of a
+// host.
+#[derive(Clone)]
+pub(crate) struct Tls {
+    connector: TlsConnector,
+    server_name: ServerName,
+}
+
+impl Tls {
+    // `alpn` is the list of application protocols to offer the server, e.g.
+    // `h2` for gRPC.
+    pub(crate) fn new(
+        config: &TlsConfig,
+        host: &str,
+        alpn: &[&[u8]],
+    ) -> LoggerResult<Self> {
+        let domain_name = config.domain_name.as_deref().unwrap_or(host);
+        let server_name = ServerName::try_from(domain_name).map_err(|_| {
+            LoggerError::TlsError(format!("Invalid domain name {domain_name:?}"))
+        })?;
+
+        let verifier: Arc<dyn ServerCertVerifier> = match &config.pinned_fingerprint {
+            Some(fingerprint) =>
+                Arc::new(PinnedCertificate(parse_fingerprint(fingerprint)?)),
+            None => Arc::new(WebPkiVerifier::new(root_certificates(config)?, None)),
+        };
+
+        let builder = ClientConfig::builder()
+            .with_safe_defaults()
+            .with_custom_certificate_verifier(verifier);
+
+        let mut client_config = match (&config.client_cert, &config.client_key) {
+            (Some(cert), Some(key)) => builder
+                .with_single_cert(read_certificates(cert)?, read_private_key(key)?)
+                .map_err(|e| LoggerError::TlsError(e.to_string()))?,
+            (None, None) => builder.with_no_client_auth(),
+            _ =>
+                return Err(LoggerError::TlsError(
+                    "client_cert and client_key have to be set together".to_string(),
+                )),
+        };
+
+        client_config.alpn_protocols =
+            alpn.iter().map(|protocol| protocol.to_vec()).collect();
+
+        Ok(Self {
+            connector: TlsConnector::from(Arc::new(client_config)),
+            server_name,
+        })
+    }
+
+    pub(crate) async fn connect(
+        self,
+        address: SocketAddr,
+    ) -> io::Result<TlsStream<TcpStream>> {
+        let stream = TcpStream::connect(address).await?;
+
+        self.wrap(stream).await
+    }
+
+    pub(crate) async fn wrap(
+        self,
+        stream: TcpStream,
+    ) -> io::Result<TlsStream<TcpStream>> {
+        self.connector.connect(self.server_name, stream).await
+    }
+}
+
+// Wraps `stream` in TLS for `host` with the options in `LoggerConfig::tls`, for
+// the transports that speak HTTP/1.1 rather than gRPC.
+#[cfg(any(feature = "http", feature = "websocket"))]
+pub(crate) async fn wrap_stream(
+    stream: TcpStream,
+    host: &str,
+    config: &crate::LoggerConfig,
+) -> LoggerResult<TlsStream<TcpStream>> {
+    let tls = Tls::new(
+        &config.tls.clone().unwrap_or_default(),
+        host,
+        &[b"http/1.1"],
+    )?;
+
+    tls.wrap(stream).await.map_err(|e| match handshake_error(&e) {
+        Some(reason) => LoggerError::TlsError(reason),
+        None => e.into(),
+    })
+}
+
+fn root_certificates(config: &TlsConfig) -> LoggerResult<RootCertStore> {
+    let mut roots = RootCertStore::empty();
+
+    match &config.ca_cert {
+        Some(pem) => {
+            let certificates: Vec<_> = read_certificates(pem)?
+                .into_iter()
+                .map(|certificate| certificate.0)
+                .collect();
+            roots.add_parsable_certificates(&certificates);
+
+            if roots.is_empty() {
+                return Err(LoggerError::TlsError(
+                    "No valid CA certificates in ca_cert".to_string(),
+                ));
+            }
+        },
+        None => roots.add_server_trust_anchors(
+            webpki_roots::TLS_SERVER_ROOTS.0.iter().map(|anchor| {
+                OwnedTrustAnchor::from_subject_spki_name_constraints(
+                    anchor.subject,
+                    anchor.spki,
+                    anchor.name_constraints,
+                )
+            }),
+        ),
+    }
+
+    Ok(roots)
+}
+
+fn read_pem(pem: &Pem) -> LoggerResult<Vec<Item>> {
+    Ok(match pem {
+        Pem::Path(path) =>
+            rustls_pemfile::read_all(&mut BufReader::new(File::open(path)?))?,
+        Pem::Pem(pem) => rustls_pemfile::read_all(&mut pem.as_bytes())?,
+    })
+}
+
+fn read_certificates(pem: &Pem) -> LoggerResult<Vec<Certificate>> {
+    Ok(read_pem(pem)?
+        .into_iter()
+        .filter_map(|item| match item {
+            Item::X509Certificate(certificate) => Some(Certificate(certificate)),
+            _ => None,
+        })
+        .collect())
+}
+
+fn read_private_key(pem: &Pem) -> LoggerResult<PrivateKey> {
+    read_pem(pem)?
+        .into_iter()
+        .find_map(|item| match item {
+            Item::RSAKey(key) | Item::PKCS8Key(key) | Item::ECKey(key) =>
+                Some(PrivateKey(key)),
+            _ => None,
+        })
+        .ok_or_else(|| LoggerError::TlsError("No private key in client_key".to_string()))
+}
+
+// Looks for a TLS error anywhere in the chain of sources of `error`.
+// `io::Error` doesn't return the error it wraps as its source, so that has to
+// be unwrapped by hand.
+pub(crate) fn handshake_error(error: &(dyn Error + 'static)) -> Option<String> {
+    let mut source = Some(error);
+
+    while let Some(error) = source {
+        let inner = error
+            .downcast_ref::<io::Error>()
+            .and_then(io::Error::get_ref)
+            .and_then(|inner| inner.downcast_ref::<rustls::Error>());
+
+        if let Some(tls_error) = error.downcast_ref::<rustls::Error>().or(inner) {
+            return Some(tls_error.to_string());
+        }
+
+        source = error.source();
+    }
+
+    None
+}
+
+pub(crate) fn parse_fingerprint(fingerprint: &str) -> LoggerResult<[u8; 32]> {
+    let invalid =
+        || LoggerError::TlsError(format!("Invalid SHA-256 fingerprint {fingerprint:?}"));
+
+    let hex: Vec<_> = fingerprint.bytes().filter(|&byte| byte != b':').collect();
+
+    if hex.len() != 64 {
+        return Err(invalid());
+    }
+
+    let mut bytes = [0; 32];
+
+    for (byte, pair) in bytes.iter_mut().zip(hex.chunks(2)) {
+        let pair = std::str::from_utf8(pair).map_err(|_| invalid())?;
+        *byte = u8::from_str_radix(pair, 16).map_err(|_| invalid())?;
+    }
+
+    Ok(bytes)
+}
+
+// Accepts the server's certificate only if it's the pinned one, regardless of
+// who signed it.
+struct PinnedCertificate([u8; 32]);
+
+impl ServerCertVerifier for PinnedCertificate {
+    fn verify_server_cert(
+        &self,
+        end_entity: &Certificate,
+        _intermediates: &[Certificate],
+        _server_name: &ServerName,
+        _scts: &mut dyn Iterator<Item = &[u8]>,
+        _ocsp_response: &[u8],
+        _now: SystemTime,
+    ) -> Result<ServerCertVerified, rustls::Error> {
+        if Sha256::digest(&end_entity.0).as_slice() == self.0 {
+            Ok(ServerCertVerified::assertion())
+        } else {
+            Err(rustls::Error::InvalidCertificateData(
+                "Certificate doesn't match the pinned fingerprint".into(),
+            ))
+        }
+    }
+}
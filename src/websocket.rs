@@ -0,0 +1,274 @@
+use crate::{LoggerConfig, LoggerResult};
+use codectrl_protobuf_bindings::data::Log;
+
+#[cfg(feature = "websocket")]
+use crate::{
+    check_payload_size, encode_payload, refuse_cleartext_auth, signature, LoggerError,
+    ProxyConfig, SharedSerializer,
+};
+#[cfg(feature = "websocket")]
+use futures_util::{stream::SplitSink, SinkExt, StreamExt};
+#[cfg(feature = "websocket")]
+use tokio::{
+    io::{AsyncRead, AsyncWrite},
+    net::TcpStream,
+    task::JoinHandle,
+};
+#[cfg(feature = "websocket")]
+use tokio_tungstenite::{
+    tungstenite::{
+        client::IntoClientRequest,
+        http::{header, HeaderValue},
+        Error as WsError, Message,
+    },
+    WebSocketStream,
+};
+
+#[cfg(feature = "websocket")]
+#[cfg(feature = "socks")]
+use crate::proxy;
+#[cfg(feature = "websocket")]
+#[cfg(feature = "tls")]
+use crate::tls;
+
+// Opens a connection to `url` just for sending `logs`.
+#[cfg(feature = "websocket")]
+pub(crate) async fn send_logs<'a>(
+    url: &str,
+    logs: impl IntoIterator<Item = &'a Log>,
+    config: &LoggerConfig,
+) -> LoggerResult<()> {
+    let mut websocket = WebSocket::connect(url, config).await?;
+
+    for log in logs {
+        websocket.send(log).await?;
+    }
+
+    // The logs have been sent by now, so failing to close the connection
+    // cleanly doesn't matter.
+    let _ = websocket.close().await;
+
+    Ok(())
+}
+
+#[cfg(not(feature = "websocket"))]
+pub(crate) async fn send_logs<'a>(
+    _url: &str,
+    _logs: impl IntoIterator<Item = &'a Log>,
+    _config: &LoggerConfig,
+) -> LoggerResult<()> {
+    Err(crate::LoggerError::LoggerError(
+        "Sending logs over WebSocket requires the `websocket` feature to be enabled"
+            .to_string(),
+    ))
+}
+
+#[cfg(feature = "websocket")]
+trait Io: AsyncRead + AsyncWrite + Unpin + Send {}
+
+#[cfg(feature = "websocket")]
+impl<T: AsyncRead + AsyncWrite + Unpin + Send> Io for T {}
+
+// A WebSocket connection that logs are sent over as binary messages, each
+// holding one CBOR-encoded log, encrypted if `LoggerConfig::encryption_key` is
+// set, and preceded by its 32-byte signature if `LoggerConfig::hmac_key` is
+// set.
+#[cfg(feature = "websocket")]
+pub(crate) struct WebSocket {
+    sink: SplitSink<WebSocketStream<Box<dyn Io>>, Message>,
+    // Reads whatever the server sends for as long as the connection is open.
+    reader: JoinHandle<()>,
+    serializer: SharedSerializer,
+    encryption_key: Option<String>,
+    hmac_key: Option<String>,
+    max_payload_size: usize,
+    // Each log is serialised into this, so that it only grows once rather than
+    // being allocated anew for every log.
+    scratch: Vec<u8>,
+}
+
+#[cfg(feature = "websocket")]
+impl WebSocket {
+    pub(crate) async fn connect(url: &str, config: &LoggerConfig) -> LoggerResult<Self> {
+        let invalid = |reason: String| {
+            LoggerError::LoggerError(format!("Invalid WebSocket URL {url:?}: {reason}"))
+        };
+
+        let mut request = url
+            .into_client_request()
+            .map_err(|e| invalid(format!("{e}")))?;
+        let uri = request.uri().clone();
+
+        let secure = match uri.scheme_str() {
+            Some("ws") => false,
+            Some("wss") => true,
+            _ => return Err(invalid("Only ws:// and wss:// URLs are supported".into())),
+        };
+
+        // They would end up in the `Host` header otherwise.
+        if uri
+            .authority()
+            .is_some_and(|authority| authority.as_str().contains('@'))
+        {
+            return Err(invalid(
+                "Credentials in the URL aren't supported, set auth_token instead".into(),
+            ));
+        }
+
+        // IPv6 addresses are written in brackets in URLs, but not when
+        // connecting.
+        let host = uri
+            .host()
+            .ok_or_else(|| invalid("Missing host".into()))?
+            .trim_start_matches('[')
+            .trim_end_matches(']');
+        let port = uri.port_u16().unwrap_or(if secure { 443 } else { 80 });
+
+        refuse_cleartext_auth(host, secure, config)?;
+
+        if let Some(token) = &config.auth_token {
+            let value = HeaderValue::from_str(&format!("Bearer {token}")).map_err(|e| {
+                LoggerError::LoggerError(format!("Invalid auth token: {e}"))
+            })?;
+            request.headers_mut().insert(header::AUTHORIZATION, value);
+        }
+
+        let stream = match &config.proxy {
+            Some(proxy) => connect_proxied(proxy, host, port).await?,
+            None => TcpStream::connect((host, port)).await?,
+        };
+        let stream = if secure {
+            wrap_tls(stream, host, config).await?
+        } else {
+            Box::new(stream)
+        };
+
+        // Checks the server's `Sec-WebSocket-Accept` header along the way.
+        let (websocket, _) = tokio_tungstenite::client_async(request, stream)
+            .await
+            .map_err(handshake_error)?;
+        let (sink, mut incoming) = websocket.split();
+
+        // The server isn't expected to send any data, but reading is what
+        // answers its pings and its close frame, so that it doesn't drop a
+        // connection that's idle between logs. Ends once the connection is
+        // closed, after which sending fails and the connection is replaced.
+        let reader = tokio::spawn(async move {
+            while let Some(Ok(_)) = incoming.next().await {}
+        });
+
+        Ok(Self {
+            sink,
+            reader,
+            serializer: config.serializer.clone(),
+            encryption_key: config.encryption_key.clone(),
+            hmac_key: config.hmac_key.clone(),
+            max_payload_size: config.max_payload_size,
+            scratch: Vec::new(),
+        })
+    }
+
+    pub(crate) async fn send(&mut self, log: &Log) -> LoggerResult<()> {
+        let payload = &mut self.scratch;
+        encode_payload(
+            log,
+            &self.serializer,
+            self.encryption_key.as_deref(),
+            payload,
+        )?;
+
+        // Signed after encrypting, so that tampered payloads are rejected
+        // before being decrypted.
+        if let Some(key) = &self.hmac_key {
+            let signature = signature::sign(key, payload)?;
+            payload.splice(0..0, signature);
+        }
+
+        check_payload_size(payload.len(), self.max_payload_size)?;
+
+        // Messages own their bytes, so they're copied out at their exact size,
+        // leaving the buffer with the capacity it grew to for the next log.
+        self.sink.send(Message::Binary(payload.clone())).await?;
+
+        Ok(())
+    }
+
+    // Sends a close frame.
+    pub(crate) async fn close(mut self) -> LoggerResult<()> {
+        self.sink.close().await?;
+
+        Ok(())
+    }
+}
+
+#[cfg(feature = "websocket")]
+impl Drop for WebSocket {
+    fn drop(&mut self) { self.reader.abort(); }
+}
+
+// Turns a refused handshake into the same errors as the HTTP transport uses.
+#[cfg(feature = "websocket")]
+fn handshake_error(e: WsError) -> LoggerError {
+    match e {
+        WsError::Http(response) => {
+            let status = response.status().as_u16();
+            let body = response.into_body().unwrap_or_default();
+
+            if status == 401 {
+                LoggerError::Unauthorized(body)
+            } else {
+                LoggerError::Rejected { status, body }
+            }
+        },
+        e => e.into(),
+    }
+}
+
+#[cfg(feature = "websocket")]
+#[cfg(feature = "tls")]
+async fn wrap_tls(
+    stream: TcpStream,
+    host: &str,
+    config: &LoggerConfig,
+) -> LoggerResult<Box<dyn Io>> {
+    Ok(Box::new(tls::wrap_stream(stream, host, config).await?))
+}
+
+#[cfg(feature = "websocket")]
+#[cfg(not(feature = "tls"))]
+async fn wrap_tls(
+    _stream: TcpStream,
+    _host: &str,
+    _config: &LoggerConfig,
+) -> LoggerResult<Box<dyn Io>> {
+    Err(LoggerError::TlsError(
+        "wss:// URLs require the `tls` feature to be enabled".to_string(),
+    ))
+}
+
+#[cfg(feature = "websocket")]
+#[cfg(feature = "socks")]
+async fn connect_proxied(
+    proxy: &ProxyConfig,
+    host: &str,
+    port: u16,
+) -> LoggerResult<TcpStream> {
+    proxy::connect(proxy, host, port)
+        .await
+        .map_err(|e| match proxy::proxy_error(&e) {
+            Some(reason) => LoggerError::ProxyError(reason),
+            None => e.into(),
+        })
+}
+
+#[cfg(feature = "websocket")]
+#[cfg(not(feature = "socks"))]
+async fn connect_proxied(
+    _proxy: &ProxyConfig,
+    _host: &str,
+    _port: u16,
+) -> LoggerResult<TcpStream> {
+    Err(LoggerError::ProxyError(
+        "SOCKS5 proxies require the `socks` feature to be enabled".to_string(),
+    ))
+}